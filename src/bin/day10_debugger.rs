@@ -0,0 +1,22 @@
+//! An interactive debugger for day 10 programs.
+//!
+//! Usage: `cargo run --bin day10_debugger -- <program>`, where the program is written in the
+//! assembly accepted by `days::day10::assemble`, such as the puzzle input.
+
+use aoc2022::days::day10::{assemble, Cpu, Debugger};
+use aoc2022::read_string;
+use std::path::Path;
+
+fn main() {
+    let path = std::env::args()
+        .nth(1)
+        .expect("Usage: day10_debugger <program>");
+    let source = read_string(Path::new(&path)).expect("Unable to read program. ");
+    let program = assemble(&source).expect("Unable to assemble program. ");
+
+    let stdin = std::io::stdin();
+    let mut stdout = std::io::stdout();
+    Debugger::new(Cpu::new(program))
+        .repl(stdin.lock(), &mut stdout)
+        .expect("Unable to run debugger. ");
+}
@@ -1,82 +1,1025 @@
-#[derive(Debug, Clone)]
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::io::{BufRead, Write};
+use std::str::FromStr;
+
+use crate::err::Error;
+
+/// The CPU's registers. `X` is the register used by the original puzzle.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Register {
+    X,
+    Y,
+    Z,
+    W,
+}
+
+impl Register {
+    pub const ALL: [Register; 4] = [Self::X, Self::Y, Self::Z, Self::W];
+
+    pub fn from_char(ch: char) -> Option<Register> {
+        match ch {
+            'x' => Some(Self::X),
+            'y' => Some(Self::Y),
+            'z' => Some(Self::Z),
+            'w' => Some(Self::W),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> char {
+        match self {
+            Self::X => 'x',
+            Self::Y => 'y',
+            Self::Z => 'z',
+            Self::W => 'w',
+        }
+    }
+}
+
+/// The values held in each register.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Registers([i32; 4]);
+
+impl Default for Registers {
+    /// Every register starts at 1, as X does in the puzzle.
+    fn default() -> Self {
+        Registers([1; 4])
+    }
+}
+
+impl Registers {
+    pub fn get(&self, reg: Register) -> i32 {
+        self.0[reg as usize]
+    }
+
+    pub fn set(&mut self, reg: Register, value: i32) {
+        self.0[reg as usize] = value;
+    }
+}
+
+/// A source value for an instruction: either a register or an immediate integer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operand {
+    Reg(Register),
+    Imm(i32),
+}
+
+impl Operand {
+    pub fn value(&self, registers: &Registers) -> i32 {
+        match self {
+            Self::Reg(reg) => registers.get(*reg),
+            Self::Imm(val) => *val,
+        }
+    }
+}
+
+impl Display for Operand {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Reg(reg) => write!(f, "{}", reg.name()),
+            Self::Imm(val) => write!(f, "{}", val),
+        }
+    }
+}
+
+/// The kind of an instruction, used to look up its cycle cost.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Opcode {
+    Noop,
+    Add,
+    Sub,
+    Mul,
+    Cpy,
+    Jmp,
+    Jnz,
+    Out,
+}
+
+/// The number of cycles each kind of instruction takes to complete.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CycleCosts {
+    costs: HashMap<Opcode, i32>,
+}
+
+impl Default for CycleCosts {
+    fn default() -> Self {
+        CycleCosts {
+            costs: HashMap::from([
+                (Opcode::Noop, 1),
+                (Opcode::Add, 2),
+                (Opcode::Sub, 2),
+                (Opcode::Mul, 2),
+                (Opcode::Cpy, 1),
+                (Opcode::Jmp, 1),
+                (Opcode::Jnz, 1),
+                (Opcode::Out, 1),
+            ]),
+        }
+    }
+}
+
+impl CycleCosts {
+    pub fn get(&self, opcode: Opcode) -> i32 {
+        self.costs[&opcode]
+    }
+
+    /// Sets the cost of `opcode`. Every instruction takes at least one cycle.
+    pub fn with(mut self, opcode: Opcode, cycles: i32) -> CycleCosts {
+        self.costs.insert(opcode, cycles.max(1));
+        self
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Command {
     Noop,
-    Addx(i32),
+    /// `add<r> v`: adds `v` to register `r`, e.g. `addx 3`.
+    Add(Register, Operand),
+    /// `sub<r> v`: subtracts `v` from register `r`.
+    Sub(Register, Operand),
+    /// `mul<r> v`: multiplies register `r` by `v`.
+    Mul(Register, Operand),
+    /// `cpy v r`: copies `v` into register `r`.
+    Cpy(Operand, Register),
+    /// `jmp t`: continues from instruction index `t`.
+    Jmp(usize),
+    /// `jnz v t`: continues from instruction index `t` if `v` is not zero.
+    Jnz(Operand, usize),
+    /// `out v`: writes `v` to the output.
+    Out(Operand),
+}
+
+/// What happens once an instruction completes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Effect {
+    /// The index of the next instruction to execute.
+    pub next_pc: usize,
+    pub output: Option<i32>,
 }
 
 impl Command {
-    pub fn from_str(input: &str) -> Command {
-        let segments: Vec<&str> = input.split(" ").collect();
-        match segments.first().expect("Empty string for input command. ") {
-            &"noop" => Self::Noop,
-            &"addx" => {
-                let operand = segments[1]
-                    .parse::<i32>()
-                    .expect("Addx operand is not an integer. ");
-                Self::Addx(operand)
-            }
-            _ => panic!("Unknown command. "),
+    pub fn opcode(&self) -> Opcode {
+        match self {
+            Self::Noop => Opcode::Noop,
+            Self::Add(..) => Opcode::Add,
+            Self::Sub(..) => Opcode::Sub,
+            Self::Mul(..) => Opcode::Mul,
+            Self::Cpy(..) => Opcode::Cpy,
+            Self::Jmp(..) => Opcode::Jmp,
+            Self::Jnz(..) => Opcode::Jnz,
+            Self::Out(..) => Opcode::Out,
         }
     }
 
+    /// The number of cycles taken with the default costs.
     pub fn cycles(&self) -> i32 {
-        match self {
-            Self::Noop => 1,
-            Self::Addx(_) => 2,
+        self.cycles_with(&CycleCosts::default())
+    }
+
+    pub fn cycles_with(&self, costs: &CycleCosts) -> i32 {
+        costs.get(self.opcode())
+    }
+
+    /// Applies the instruction at index `pc` to the registers, returning where to go next.
+    /// Arithmetic wraps on overflow.
+    pub fn process(&self, registers: &mut Registers, pc: usize) -> Effect {
+        let mut effect = Effect {
+            next_pc: pc + 1,
+            output: None,
+        };
+        match *self {
+            Self::Noop => {}
+            Self::Add(reg, val) => {
+                registers.set(reg, registers.get(reg).wrapping_add(val.value(registers)))
+            }
+            Self::Sub(reg, val) => {
+                registers.set(reg, registers.get(reg).wrapping_sub(val.value(registers)))
+            }
+            Self::Mul(reg, val) => {
+                registers.set(reg, registers.get(reg).wrapping_mul(val.value(registers)))
+            }
+            Self::Cpy(val, reg) => registers.set(reg, val.value(registers)),
+            Self::Jmp(target) => effect.next_pc = target,
+            Self::Jnz(val, target) => {
+                if val.value(registers) != 0 {
+                    effect.next_pc = target;
+                }
+            }
+            Self::Out(val) => effect.output = Some(val.value(registers)),
+        }
+        effect
+    }
+
+    /// Parses a single instruction, resolving jump targets through `labels`. Errors are
+    /// reported at `line`, with columns offset by `offset`.
+    fn parse_with_labels(
+        input: &str,
+        labels: &HashMap<String, usize>,
+        line: usize,
+        offset: usize,
+    ) -> Result<Command, Error> {
+        let error = |column: usize, message: String| Error::ParseError {
+            line,
+            column: offset + column,
+            message,
+        };
+        let tokens = tokens_with_columns(input);
+        let (mnemonic_col, mnemonic) = *tokens
+            .first()
+            .ok_or_else(|| error(1, String::from("Empty instruction. ")))?;
+        let operand = |i: usize| {
+            let (column, token) = *tokens.get(i).ok_or_else(|| {
+                error(
+                    input.len() + 1,
+                    format!("Missing operand for '{}'. ", mnemonic),
+                )
+            })?;
+            parse_operand(token)
+                .ok_or_else(|| error(column, format!("Invalid operand '{}'. ", token)))
+        };
+        let register = |i: usize| match operand(i)? {
+            Operand::Reg(reg) => Ok(reg),
+            Operand::Imm(_) => Err(error(
+                tokens[i].0,
+                format!("Expected a register, found '{}'. ", tokens[i].1),
+            )),
+        };
+        let target = |i: usize| {
+            let (column, token) = *tokens.get(i).ok_or_else(|| {
+                error(
+                    input.len() + 1,
+                    format!("Missing jump target for '{}'. ", mnemonic),
+                )
+            })?;
+            token
+                .parse::<usize>()
+                .ok()
+                .or_else(|| labels.get(token).cloned())
+                .ok_or_else(|| error(column, format!("Unknown label '{}'. ", token)))
+        };
+
+        let (command, n_operands) = match mnemonic {
+            "noop" => (Self::Noop, 0),
+            "jmp" => (Self::Jmp(target(1)?), 1),
+            "jnz" => (Self::Jnz(operand(1)?, target(2)?), 2),
+            "cpy" => (Self::Cpy(operand(1)?, register(2)?), 2),
+            "out" => (Self::Out(operand(1)?), 1),
+            _ => {
+                let reg = mnemonic
+                    .chars()
+                    .last()
+                    .and_then(Register::from_char)
+                    .filter(|_| mnemonic.len() == 4);
+                match (mnemonic.get(..3), reg) {
+                    (Some("add"), Some(reg)) => (Self::Add(reg, operand(1)?), 1),
+                    (Some("sub"), Some(reg)) => (Self::Sub(reg, operand(1)?), 1),
+                    (Some("mul"), Some(reg)) => (Self::Mul(reg, operand(1)?), 1),
+                    _ => {
+                        return Err(error(
+                            mnemonic_col,
+                            format!("Unknown instruction '{}'. ", mnemonic),
+                        ))
+                    }
+                }
+            }
+        };
+
+        match tokens.get(n_operands + 1) {
+            Some((column, token)) => Err(error(*column, format!("Unexpected '{}'. ", token))),
+            None => Ok(command),
         }
     }
+}
 
-    pub fn process(&self, input: &i32) -> i32 {
+impl FromStr for Command {
+    type Err = Error;
+
+    /// Parses a single instruction. Jump targets must be instruction indices; use
+    /// [`assemble`] for programs with labels.
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        Command::parse_with_labels(input, &HashMap::new(), 1, 0)
+    }
+}
+
+impl Display for Command {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            Self::Noop => input.clone(),
-            Self::Addx(ref val) => input + val,
+            Self::Noop => write!(f, "noop"),
+            Self::Add(reg, val) => write!(f, "add{} {}", reg.name(), val),
+            Self::Sub(reg, val) => write!(f, "sub{} {}", reg.name(), val),
+            Self::Mul(reg, val) => write!(f, "mul{} {}", reg.name(), val),
+            Self::Cpy(val, reg) => write!(f, "cpy {} {}", val, reg.name()),
+            Self::Jmp(target) => write!(f, "jmp {}", target),
+            Self::Jnz(val, target) => write!(f, "jnz {} {}", val, target),
+            Self::Out(val) => write!(f, "out {}", val),
         }
     }
 }
 
-pub fn value_at_cycle(commands: &Vec<Command>, target_cycle: i32) -> i32 {
-    let mut cycles = 0;
-    let mut x = 1;
+fn parse_operand(token: &str) -> Option<Operand> {
+    let mut chars = token.chars();
+    match (chars.next().and_then(Register::from_char), chars.next()) {
+        (Some(reg), None) => Some(Operand::Reg(reg)),
+        _ => token.parse::<i32>().ok().map(Operand::Imm),
+    }
+}
 
-    for com in commands {
-        cycles += com.cycles();
-        if cycles >= target_cycle {
-            break;
+/// Splits on whitespace, pairing each token with its 1-based column.
+fn tokens_with_columns(input: &str) -> Vec<(usize, &str)> {
+    let mut tokens = vec![];
+    let mut start = None;
+    for (i, ch) in input.char_indices().chain([(input.len(), ' ')]) {
+        match (ch.is_whitespace(), start) {
+            (true, Some(st)) => {
+                tokens.push((st + 1, &input[st..i]));
+                start = None;
+            }
+            (false, None) => start = Some(i),
+            _ => {}
         }
-        x = com.process(&x)
     }
-    x
+    tokens
 }
 
-pub fn total_cycles(commands: &Vec<Command>) -> i32 {
-    commands.iter().map(|com| com.cycles()).sum()
+/// Assembles a program, one instruction per line. Comments run from `;` or `#` to the end of
+/// the line, and a line may start with a `label:` that jump instructions can refer to.
+pub fn assemble(source: &str) -> Result<Vec<Command>, Error> {
+    // Strip comments and labels first, so that forward references can be resolved.
+    let mut labels: HashMap<String, usize> = HashMap::new();
+    let mut lines = vec![];
+    for (line_no, line) in source.lines().enumerate() {
+        let code = line.split([';', '#']).next().unwrap();
+        let (code, offset) = match code.split_once(':') {
+            Some((label, rest)) => {
+                let label_col = label.len() - label.trim_start().len() + 1;
+                let label = label.trim();
+                if label.is_empty() || label.contains(char::is_whitespace) {
+                    return Err(Error::ParseError {
+                        line: line_no + 1,
+                        column: label_col,
+                        message: format!("Invalid label '{}'. ", label),
+                    });
+                }
+                // Numeric jump targets are instruction indices, so a numeric label could never
+                // be referred to.
+                if label.parse::<usize>().is_ok() {
+                    return Err(Error::ParseError {
+                        line: line_no + 1,
+                        column: label_col,
+                        message: format!("Label '{}' cannot be a number. ", label),
+                    });
+                }
+                if labels.insert(String::from(label), lines.len()).is_some() {
+                    return Err(Error::ParseError {
+                        line: line_no + 1,
+                        column: label_col,
+                        message: format!("Duplicate label '{}'. ", label),
+                    });
+                }
+                (rest, code.len() - rest.len())
+            }
+            None => (code, 0),
+        };
+        if !code.trim().is_empty() {
+            lines.push((line_no + 1, offset, code));
+        }
+    }
+
+    lines
+        .into_iter()
+        .map(|(line, offset, code)| Command::parse_with_labels(code, &labels, line, offset))
+        .collect()
 }
 
-pub fn render_image(commands: &Vec<Command>) -> String {
-    let width: i32 = 40;
+/// Pretty-prints a program in a form that [`assemble`] accepts, labelling every jump target
+/// and annotating each instruction with its index and cycle cost.
+pub fn disassemble(program: &[Command], costs: &CycleCosts) -> String {
+    let mut targets: Vec<usize> = program
+        .iter()
+        .filter_map(|command| match command {
+            Command::Jmp(target) | Command::Jnz(_, target) => Some(*target),
+            _ => None,
+        })
+        .collect();
+    targets.sort_unstable();
+    targets.dedup();
+    let label = |target: usize| format!("L{}", target);
+
     let mut output = String::new();
-    for cyc in 1..total_cycles(commands) {
-        let val = value_at_cycle(commands, cyc as i32);
-        let curr_pixel = (cyc as i32 - 1) % width;
-        // Check that the current pixel is within 1 pixel of the cursor position.
-        if (curr_pixel - val).abs() < 2 {
-            output.push('#');
-        } else {
-            output.push('.');
+    for (i, command) in program.iter().enumerate() {
+        if targets.binary_search(&i).is_ok() {
+            output += &format!("{}:\n", label(i));
         }
+        let text = match command {
+            Command::Jmp(target) => format!("jmp {}", label(*target)),
+            Command::Jnz(val, target) => format!("jnz {} {}", val, label(*target)),
+            other => other.to_string(),
+        };
+        output += &format!(
+            "    {:<16}; {:>4}  ({} cycles)\n",
+            text,
+            i,
+            command.cycles_with(costs)
+        );
+    }
+    // Jumps past the end of the program halt it, but still need a label to reassemble.
+    for target in targets.iter().filter(|target| **target >= program.len()) {
+        output += &format!("{}:\n", label(*target));
+    }
+    output
+}
+
+/// An instruction that has been fetched but has not yet finished executing.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InFlight {
+    pub command: Command,
+    /// The number of cycles left before the instruction completes.
+    pub remaining: i32,
+}
+
+/// A CPU that executes a program one clock cycle at a time.
+#[derive(Debug, Clone)]
+pub struct Cpu {
+    program: Vec<Command>,
+    costs: CycleCosts,
+    pc: usize,
+    registers: Registers,
+    cycle: i32,
+    in_flight: Option<InFlight>,
+    output: Vec<i32>,
+}
+
+impl Cpu {
+    pub fn new(program: Vec<Command>) -> Cpu {
+        Cpu::with_costs(program, CycleCosts::default())
+    }
 
-        // If we are at the end of a line, we want to output a newline.
-        if curr_pixel == width - 1 {
-            output.push('\n');
+    pub fn with_costs(program: Vec<Command>, costs: CycleCosts) -> Cpu {
+        Cpu {
+            program,
+            costs,
+            pc: 0,
+            registers: Registers::default(),
+            cycle: 0,
+            in_flight: None,
+            output: vec![],
         }
     }
-    output
+
+    pub fn program(&self) -> &[Command] {
+        &self.program
+    }
+
+    /// The current value of the X register.
+    pub fn x(&self) -> i32 {
+        self.registers.get(Register::X)
+    }
+
+    pub fn registers(&self) -> &Registers {
+        &self.registers
+    }
+
+    /// Every value written by `out` so far.
+    pub fn output(&self) -> &[i32] {
+        &self.output
+    }
+
+    /// The number of cycles completed so far.
+    pub fn cycle(&self) -> i32 {
+        self.cycle
+    }
+
+    /// The index of the instruction currently executing, or the next to be fetched.
+    pub fn pc(&self) -> usize {
+        self.pc
+    }
+
+    pub fn in_flight(&self) -> Option<&InFlight> {
+        self.in_flight.as_ref()
+    }
+
+    pub fn is_halted(&self) -> bool {
+        self.in_flight.is_none() && self.pc >= self.program.len()
+    }
+
+    /// Runs a single clock cycle, returning the cycle number and the value of X during it,
+    /// or `None` once the program has finished. Instructions only take effect as they
+    /// complete, at the end of their final cycle.
+    pub fn step(&mut self) -> Option<(i32, i32)> {
+        if self.in_flight.is_none() {
+            let command = *self.program.get(self.pc)?;
+            self.in_flight = Some(InFlight {
+                remaining: command.cycles_with(&self.costs),
+                command,
+            });
+        }
+
+        self.cycle += 1;
+        let x_during = self.x();
+        let in_flight = self.in_flight.as_mut().unwrap();
+        in_flight.remaining -= 1;
+        if in_flight.remaining <= 0 {
+            let effect = in_flight.command.process(&mut self.registers, self.pc);
+            self.output.extend(effect.output);
+            self.in_flight = None;
+            self.pc = effect.next_pc;
+        }
+        Some((self.cycle, x_during))
+    }
+
+    /// Iterates over the remaining cycles, yielding `(cycle, x_during)` for each.
+    pub fn cycles(&mut self) -> impl Iterator<Item = (i32, i32)> + '_ {
+        std::iter::from_fn(move || self.step())
+    }
+}
+
+/// The value of X during `target_cycle`, or its final value if the program ends first.
+pub fn value_at_cycle(commands: &[Command], target_cycle: i32) -> i32 {
+    let mut cpu = Cpu::new(commands.to_vec());
+    let during = cpu
+        .cycles()
+        .find(|(cycle, _)| *cycle == target_cycle)
+        .map(|(_, x)| x);
+    during.unwrap_or(cpu.x())
+}
+
+/// The sum of the signal strengths, cycle number times X, over `sample_cycles`. The program
+/// only runs as far as the last sample, so it need not halt.
+pub fn signal_strength(commands: &[Command], sample_cycles: &[i32]) -> i32 {
+    let last = sample_cycles.iter().copied().max().unwrap_or(0);
+    Cpu::new(commands.to_vec())
+        .cycles()
+        .take_while(|(cycle, _)| *cycle <= last)
+        .filter(|(cycle, _)| sample_cycles.contains(cycle))
+        .map(|(cycle, x)| cycle * x)
+        .sum()
+}
+
+/// The number of cycles a program takes with the default costs, assuming it never jumps.
+pub fn total_cycles(commands: &[Command]) -> i32 {
+    commands.iter().map(|com| com.cycles()).sum()
+}
+
+/// Renders the program's output on the default 40 by 6 CRT, as `#` and `.` characters.
+pub fn render_image(commands: &[Command]) -> String {
+    let crt = Crt::default();
+    crt.to_text(&crt.render(commands))
+}
+
+/// The geometry of a CRT, the width of the sprite drawn at X, and how pixels are shown as
+/// text. The beam draws one pixel per cycle, left to right and top to bottom.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Crt {
+    pub width: usize,
+    pub height: usize,
+    /// The number of pixels covered by the sprite, centred on X.
+    pub sprite_width: usize,
+    pub lit: char,
+    pub dark: char,
+}
+
+impl Default for Crt {
+    fn default() -> Self {
+        Crt {
+            width: 40,
+            height: 6,
+            sprite_width: 3,
+            lit: '#',
+            dark: '.',
+        }
+    }
+}
+
+impl Crt {
+    pub fn new(width: usize, height: usize) -> Crt {
+        Crt {
+            width,
+            height,
+            ..Default::default()
+        }
+    }
+
+    /// Whether the sprite, centred on `x`, covers `column`. Even-width sprites extend one
+    /// pixel further to the right than to the left.
+    pub fn is_lit(&self, column: usize, x: i32) -> bool {
+        let left = x as i64 - (self.sprite_width as i64 - 1) / 2;
+        let column = column as i64;
+        column >= left && column < left + self.sprite_width as i64
+    }
+
+    /// Draws the pixel for `cycle` onto `bitmap`, given the value of X during it. Rows are
+    /// added as the beam reaches them, and cycles past the bottom of the screen are ignored.
+    pub fn draw(&self, bitmap: &mut Vec<Vec<bool>>, cycle: i32, x: i32) {
+        let index = (cycle - 1) as usize;
+        if cycle < 1 || self.width == 0 || index >= self.width * self.height {
+            return;
+        }
+
+        let (row, column) = (index / self.width, index % self.width);
+        if bitmap.len() <= row {
+            bitmap.resize(row + 1, vec![]);
+        }
+        if bitmap[row].len() <= column {
+            bitmap[row].resize(column + 1, false);
+        }
+        bitmap[row][column] = self.is_lit(column, x);
+    }
+
+    /// Draws the `(cycle, x_during)` pairs of a cycle stream, returning a full `height` by
+    /// `width` bitmap indexed as `bitmap[row][column]`. Only one screen's worth of cycles is
+    /// read, so the stream may be endless. Pixels never reached by the beam are dark.
+    pub fn render_cycles<I: IntoIterator<Item = (i32, i32)>>(&self, cycles: I) -> Vec<Vec<bool>> {
+        let mut bitmap = vec![];
+        for (cycle, x) in cycles.into_iter().take(self.width * self.height) {
+            self.draw(&mut bitmap, cycle, x);
+        }
+        bitmap.resize(self.height, vec![]);
+        for row in bitmap.iter_mut() {
+            row.resize(self.width, false);
+        }
+        bitmap
+    }
+
+    pub fn render(&self, commands: &[Command]) -> Vec<Vec<bool>> {
+        self.render_cycles(Cpu::new(commands.to_vec()).cycles())
+    }
+
+    /// Shows a bitmap as text, with each row ended by a newline.
+    pub fn to_text(&self, bitmap: &[Vec<bool>]) -> String {
+        bitmap
+            .iter()
+            .map(|row| {
+                row.iter()
+                    .map(|lit| if *lit { self.lit } else { self.dark })
+                    .chain(['\n'])
+                    .collect::<String>()
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Comparison {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl Comparison {
+    pub fn from_symbol(input: &str) -> Option<Comparison> {
+        match input {
+            "==" => Some(Self::Eq),
+            "!=" => Some(Self::Ne),
+            "<" => Some(Self::Lt),
+            "<=" => Some(Self::Le),
+            ">" => Some(Self::Gt),
+            ">=" => Some(Self::Ge),
+            _ => None,
+        }
+    }
+
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            Self::Eq => "==",
+            Self::Ne => "!=",
+            Self::Lt => "<",
+            Self::Le => "<=",
+            Self::Gt => ">",
+            Self::Ge => ">=",
+        }
+    }
+
+    pub fn holds(&self, lhs: i32, rhs: i32) -> bool {
+        match self {
+            Self::Eq => lhs == rhs,
+            Self::Ne => lhs != rhs,
+            Self::Lt => lhs < rhs,
+            Self::Le => lhs <= rhs,
+            Self::Gt => lhs > rhs,
+            Self::Ge => lhs >= rhs,
+        }
+    }
+}
+
+/// A condition on which the [`Debugger`] stops. Each describes the state the CPU is about to
+/// run in, so that stopping at `Cycle(20)` shows X as it will be during cycle 20.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Breakpoint {
+    /// Stop before cycle `n` runs.
+    Cycle(i32),
+    /// Stop before the instruction at index `n` is fetched.
+    Instruction(usize),
+    /// Stop whenever the register comparison holds.
+    Register(Register, Comparison, i32),
+}
+
+impl Breakpoint {
+    pub fn hit(&self, cpu: &Cpu) -> bool {
+        match *self {
+            Self::Cycle(n) => cpu.cycle() + 1 == n,
+            Self::Instruction(n) => cpu.in_flight().is_none() && cpu.pc() == n,
+            Self::Register(reg, cmp, val) => cmp.holds(cpu.registers().get(reg), val),
+        }
+    }
+}
+
+impl Display for Breakpoint {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Cycle(n) => write!(f, "cycle {}", n),
+            Self::Instruction(n) => write!(f, "instruction {}", n),
+            Self::Register(reg, cmp, val) => write!(f, "{} {} {}", reg.name(), cmp.symbol(), val),
+        }
+    }
+}
+
+/// Why the [`Debugger`] handed back control.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stop {
+    /// A single step completed.
+    Stepped,
+    /// The breakpoint with this index was hit.
+    Breakpoint(usize),
+    Halted,
+}
+
+/// Runs a [`Cpu`] under control of breakpoints and watches, drawing the CRT as it goes.
+#[derive(Debug, Clone)]
+pub struct Debugger {
+    cpu: Cpu,
+    breakpoints: Vec<Option<Breakpoint>>,
+    watches: Vec<Register>,
+    crt: Crt,
+    screen: Vec<Vec<bool>>,
+    /// The cycle of the last stop, and the breakpoints already reported there.
+    reported: (i32, Vec<usize>),
+}
+
+impl Debugger {
+    pub fn new(cpu: Cpu) -> Debugger {
+        Debugger::with_crt(cpu, Crt::default())
+    }
+
+    pub fn with_crt(cpu: Cpu, crt: Crt) -> Debugger {
+        Debugger {
+            cpu,
+            breakpoints: vec![],
+            watches: vec![],
+            crt,
+            screen: vec![],
+            reported: (0, vec![]),
+        }
+    }
+
+    pub fn cpu(&self) -> &Cpu {
+        &self.cpu
+    }
+
+    /// Adds a breakpoint, returning the index used to refer to it.
+    pub fn add_breakpoint(&mut self, breakpoint: Breakpoint) -> usize {
+        self.breakpoints.push(Some(breakpoint));
+        self.breakpoints.len() - 1
+    }
+
+    pub fn remove_breakpoint(&mut self, index: usize) -> Option<Breakpoint> {
+        self.breakpoints.get_mut(index).and_then(|bp| bp.take())
+    }
+
+    /// The active breakpoints, with their indices.
+    pub fn breakpoints(&self) -> Vec<(usize, Breakpoint)> {
+        self.breakpoints
+            .iter()
+            .enumerate()
+            .filter_map(|(i, bp)| bp.map(|bp| (i, bp)))
+            .collect()
+    }
+
+    pub fn watch(&mut self, reg: Register) {
+        if !self.watches.contains(&reg) {
+            self.watches.push(reg);
+        }
+    }
+
+    pub fn unwatch(&mut self, reg: Register) {
+        self.watches.retain(|watched| *watched != reg);
+    }
+
+    /// The current values of the watched registers.
+    pub fn watches(&self) -> Vec<(Register, i32)> {
+        self.watches
+            .iter()
+            .map(|reg| (*reg, self.cpu.registers().get(*reg)))
+            .collect()
+    }
+
+    /// The CRT image drawn so far, up to the beam's current position.
+    pub fn screen(&self) -> &[Vec<bool>] {
+        &self.screen
+    }
+
+    /// The CRT image drawn so far, as text.
+    pub fn crt(&self) -> String {
+        self.crt.to_text(&self.screen)
+    }
+
+    /// Runs a single clock cycle.
+    pub fn step(&mut self) -> Stop {
+        match self.cpu.step() {
+            Some((cycle, x)) => {
+                self.crt.draw(&mut self.screen, cycle, x);
+                Stop::Stepped
+            }
+            None => Stop::Halted,
+        }
+    }
+
+    /// Runs until a breakpoint is hit or the program halts. Breakpoints are checked before
+    /// the first cycle runs, but one already reported at the current cycle is skipped, so
+    /// that continuing from a breakpoint makes progress.
+    pub fn resume(&mut self) -> Stop {
+        loop {
+            let cycle = self.cpu.cycle();
+            if self.reported.0 != cycle {
+                self.reported = (cycle, vec![]);
+            }
+            if let Some((index, _)) = self
+                .breakpoints()
+                .into_iter()
+                .find(|(i, bp)| !self.reported.1.contains(i) && bp.hit(&self.cpu))
+            {
+                self.reported.1.push(index);
+                return Stop::Breakpoint(index);
+            }
+            if self.step() == Stop::Halted {
+                return Stop::Halted;
+            }
+        }
+    }
+
+    /// Runs an interactive session, reading commands from `input` until it ends or `quit`
+    /// is entered. Type `help` for the list of commands.
+    pub fn repl<R: BufRead, W: Write>(&mut self, input: R, output: &mut W) -> std::io::Result<()> {
+        write!(output, "(day10) ")?;
+        output.flush()?;
+        for line in input.lines() {
+            let line = line?;
+            let words: Vec<&str> = line.split_whitespace().collect();
+            match words[..] {
+                [] => {}
+                ["q"] | ["quit"] => return Ok(()),
+                ["h"] | ["help"] => writeln!(output, "{}", REPL_HELP)?,
+                ["s"] | ["step"] => {
+                    let stop = self.step();
+                    self.report(stop, output)?;
+                }
+                ["s", n] | ["step", n] => match n.parse::<usize>() {
+                    Ok(n) => {
+                        let mut stop = Stop::Stepped;
+                        for _ in 0..n {
+                            stop = self.step();
+                            if stop == Stop::Halted {
+                                break;
+                            }
+                        }
+                        self.report(stop, output)?;
+                    }
+                    Err(_) => writeln!(output, "Invalid step count '{}'.", n)?,
+                },
+                ["c"] | ["continue"] => {
+                    let stop = self.resume();
+                    self.report(stop, output)?;
+                }
+                ["b"] | ["break"] => {
+                    for (i, bp) in self.breakpoints() {
+                        writeln!(output, "  {}: {}", i, bp)?;
+                    }
+                }
+                ["b", ..] | ["break", ..] => match parse_breakpoint(&words[1..]) {
+                    Some(bp) => {
+                        let i = self.add_breakpoint(bp);
+                        writeln!(output, "Breakpoint {}: {}", i, bp)?;
+                    }
+                    None => writeln!(
+                        output,
+                        "Expected 'break cycle <n>', 'break pc <n>' or 'break <reg> <cmp> <n>'."
+                    )?,
+                },
+                ["d", n] | ["delete", n] => {
+                    match n
+                        .parse::<usize>()
+                        .ok()
+                        .and_then(|n| self.remove_breakpoint(n))
+                    {
+                        Some(bp) => writeln!(output, "Deleted breakpoint {}: {}", n, bp)?,
+                        None => writeln!(output, "No breakpoint '{}'.", n)?,
+                    }
+                }
+                ["w", reg] | ["watch", reg] | ["unwatch", reg] => match parse_operand(reg) {
+                    Some(Operand::Reg(r)) if words[0] == "unwatch" => self.unwatch(r),
+                    Some(Operand::Reg(r)) => self.watch(r),
+                    _ => writeln!(output, "Unknown register '{}'.", reg)?,
+                },
+                ["r"] | ["regs"] => {
+                    for reg in Register::ALL {
+                        write!(output, "{}={} ", reg.name(), self.cpu.registers().get(reg))?;
+                    }
+                    writeln!(output)?;
+                }
+                ["crt"] => write!(output, "{}", self.crt())?,
+                ["l"] | ["list"] => {
+                    let pc = self.cpu.pc();
+                    let program = self.cpu.program();
+                    let start = pc.saturating_sub(3);
+                    for (i, command) in program.iter().enumerate().skip(start).take(pc + 4 - start)
+                    {
+                        let marker = if i == pc { "=>" } else { "  " };
+                        writeln!(output, "{} {:>4}  {}", marker, i, command)?;
+                    }
+                }
+                _ => writeln!(
+                    output,
+                    "Unknown command '{}'. Type 'help' for help.",
+                    line.trim()
+                )?,
+            }
+            write!(output, "(day10) ")?;
+            output.flush()?;
+        }
+        writeln!(output)
+    }
+
+    fn report<W: Write>(&self, stop: Stop, output: &mut W) -> std::io::Result<()> {
+        match stop {
+            Stop::Stepped => {}
+            Stop::Breakpoint(i) => writeln!(
+                output,
+                "Hit breakpoint {}: {}",
+                i,
+                self.breakpoints[i].unwrap()
+            )?,
+            Stop::Halted => writeln!(output, "Program halted.")?,
+        }
+        write!(output, "cycle {} pc {}", self.cpu.cycle(), self.cpu.pc())?;
+        if let Some(in_flight) = self.cpu.in_flight() {
+            write!(
+                output,
+                " ({}, {} cycles left)",
+                in_flight.command, in_flight.remaining
+            )?;
+        }
+        for (reg, val) in self.watches() {
+            write!(output, " {}={}", reg.name(), val)?;
+        }
+        writeln!(output)
+    }
+}
+
+const REPL_HELP: &str = "Commands:
+  step [n], s [n]         run one (or n) cycles
+  continue, c             run until a breakpoint or the end of the program
+  break, b                list breakpoints
+  break cycle <n>         stop before cycle n
+  break pc <n>            stop before fetching instruction n
+  break <reg> <cmp> <n>   stop when e.g. 'x >= 5' (cmp is one of == != < <= > >=)
+  delete <i>, d <i>       remove breakpoint i
+  watch <reg>, w <reg>    show a register after every stop
+  unwatch <reg>           stop showing a register
+  regs, r                 show all registers
+  crt                     show the CRT drawn so far
+  list, l                 show the program around the current instruction
+  quit, q                 leave the debugger";
+
+fn parse_breakpoint(words: &[&str]) -> Option<Breakpoint> {
+    match words {
+        ["cycle", n] => n.parse().ok().map(Breakpoint::Cycle),
+        ["pc", n] => n.parse().ok().map(Breakpoint::Instruction),
+        [reg, cmp, n] => match parse_operand(reg)? {
+            Operand::Reg(reg) => Some(Breakpoint::Register(
+                reg,
+                Comparison::from_symbol(cmp)?,
+                n.parse().ok()?,
+            )),
+            Operand::Imm(_) => None,
+        },
+        _ => None,
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::{render_image, value_at_cycle, Command};
+    use super::{
+        assemble, disassemble, render_image, signal_strength, total_cycles, value_at_cycle,
+        Breakpoint, Command, Comparison, Cpu, Crt, CycleCosts, Debugger, Opcode, Operand, Register,
+        Stop,
+    };
+    use crate::err::Error;
+    use crate::image;
     use crate::io;
+    use crate::ocr;
     use std::path::Path;
 
     #[test]
@@ -85,9 +1028,12 @@ mod tests {
             io::read_string_col(Path::new("data/day10/example.txt")).expect("No input found. ");
         let commands: Vec<Command> = input_vec
             .iter()
-            .map(|string| Command::from_str(string.as_str()))
+            .map(|string| {
+                string
+                    .parse::<Command>()
+                    .expect("Unable to parse command. ")
+            })
             .collect();
-        let x: i32 = 1;
 
         let c20 = value_at_cycle(&commands, 20);
         assert_eq!(c20 * 20, 420);
@@ -106,6 +1052,35 @@ mod tests {
             c20 * 20 + c60 * 60 + c100 * 100 + c140 * 140 + c180 * 180 + c220 * 220,
             13140
         );
+        assert_eq!(
+            signal_strength(&commands, &[20, 60, 100, 140, 180, 220]),
+            13140
+        );
+
+        // A program that never halts is only run as far as the last sample.
+        let forever = assemble("loop: noop\njmp loop").unwrap();
+        assert_eq!(signal_strength(&forever, &[20, 60]), 80);
+        assert_eq!(signal_strength(&forever, &[]), 0);
+    }
+
+    #[test]
+    pub fn day10_cpu_step() {
+        let commands = assemble("noop\naddx 3\naddx -5").unwrap();
+        let mut cpu = Cpu::new(commands.clone());
+
+        assert_eq!(cpu.step(), Some((1, 1)));
+        assert_eq!(cpu.step(), Some((2, 1)));
+        assert_eq!(cpu.in_flight().unwrap().remaining, 1);
+        assert_eq!(cpu.pc(), 1);
+        assert_eq!(cpu.step(), Some((3, 1)));
+        assert_eq!((cpu.x(), cpu.pc()), (4, 2));
+        assert!(cpu.in_flight().is_none());
+
+        let rest: Vec<(i32, i32)> = cpu.cycles().collect();
+        assert_eq!(rest, vec![(4, 4), (5, 4)]);
+        assert_eq!((cpu.x(), cpu.cycle()), (-1, total_cycles(&commands)));
+        assert!(cpu.is_halted());
+        assert_eq!(cpu.step(), None);
     }
 
     #[test]
@@ -114,19 +1089,15 @@ mod tests {
             io::read_string_col(Path::new("data/day10/data.txt")).expect("No input found. ");
         let commands: Vec<Command> = input_vec
             .iter()
-            .map(|string| Command::from_str(string.as_str()))
+            .map(|string| {
+                string
+                    .parse::<Command>()
+                    .expect("Unable to parse command. ")
+            })
             .collect();
-        let x: i32 = 1;
-
-        let c20 = value_at_cycle(&commands, 20);
-        let c60 = value_at_cycle(&commands, 60);
-        let c100 = value_at_cycle(&commands, 100);
-        let c140 = value_at_cycle(&commands, 140);
-        let c180 = value_at_cycle(&commands, 180);
-        let c220 = value_at_cycle(&commands, 220);
 
         assert_eq!(
-            c20 * 20 + c60 * 60 + c100 * 100 + c140 * 140 + c180 * 180 + c220 * 220,
+            signal_strength(&commands, &[20, 60, 100, 140, 180, 220]),
             17840
         );
     }
@@ -137,12 +1108,209 @@ mod tests {
             io::read_string_col(Path::new("data/day10/data.txt")).expect("No input found. ");
         let commands: Vec<Command> = input_vec
             .iter()
-            .map(|string| Command::from_str(string.as_str()))
+            .map(|string| {
+                string
+                    .parse::<Command>()
+                    .expect("Unable to parse command. ")
+            })
             .collect();
         let out = render_image(&commands);
         println!("{}", out);
 
-        // Nothing to really test here, because the output is to the command line.
-        // However the correct answer is EALGULPG.
+        // The answer provided by AOC.
+        assert_eq!(ocr::recognise(&out).unwrap(), "EALGULPG");
+    }
+
+    #[test]
+    pub fn day10_instruction_set() {
+        assert_eq!(
+            "addx -3".parse::<Command>().unwrap(),
+            Command::Add(Register::X, Operand::Imm(-3))
+        );
+        assert_eq!(
+            "muly z".parse::<Command>().unwrap(),
+            Command::Mul(Register::Y, Operand::Reg(Register::Z))
+        );
+        assert_eq!(
+            "cpy 5 w".parse::<Command>().unwrap(),
+            Command::Cpy(Operand::Imm(5), Register::W)
+        );
+        for bad in [
+            "addq 3",
+            "jmp nowhere",
+            "cpy x 3",
+            "addx",
+            "noop 1",
+            "éé 1",
+            "adé 1",
+        ] {
+            assert!(bad.parse::<Command>().is_err(), "{} should not parse", bad);
+        }
+
+        // Multiply y by 3, four times, outputting as we go.
+        let source = "
+            ; y starts at 1
+                cpy 4 z         # loop counter
+            loop:
+                muly 3
+                out y
+                subz 1
+                jnz z loop
+            end: noop
+        ";
+        let program = assemble(source).unwrap();
+        assert_eq!(program.len(), 6);
+        assert_eq!(program[4], Command::Jnz(Operand::Reg(Register::Z), 1));
+
+        let costs = CycleCosts::default().with(Opcode::Mul, 5);
+        let mut cpu = Cpu::with_costs(program.clone(), costs.clone());
+        let n_cycles = cpu.cycles().count() as i32;
+        assert_eq!(cpu.output(), &[3, 9, 27, 81]);
+        assert_eq!(cpu.registers().get(Register::Y), 81);
+        assert_eq!(n_cycles, 1 + 4 * (5 + 1 + 2 + 1) + 1);
+
+        // The disassembly should reassemble to the same program.
+        let listing = disassemble(&program, &costs);
+        assert!(listing.contains("L1:\n    muly 3"));
+        assert_eq!(assemble(&listing).unwrap(), program);
+    }
+
+    #[test]
+    pub fn day10_assembler_errors() {
+        let position_of = |source: &str| match assemble(source) {
+            Err(Error::ParseError { line, column, .. }) => (line, column),
+            other => panic!("Expected a parse error, got {:?}", other),
+        };
+        assert_eq!(position_of("noop\n  addx 1\n  bad 2"), (3, 3));
+        assert_eq!(position_of("start: noop\n  jmp finish"), (2, 7));
+        assert_eq!(position_of("a: noop\na: noop"), (2, 1));
+        assert_eq!(position_of("lbl: cpy x 7"), (1, 12));
+        assert_eq!(position_of("noop\n 10: noop\n  jmp 10"), (2, 2));
+        assert_eq!(position_of("  éé 1"), (1, 3));
+    }
+
+    #[test]
+    pub fn day10_debugger() {
+        let input_vec: Vec<String> =
+            io::read_string_col(Path::new("data/day10/example.txt")).expect("No input found. ");
+        let commands: Vec<Command> = input_vec
+            .iter()
+            .map(|string| {
+                string
+                    .parse::<Command>()
+                    .expect("Unable to parse command. ")
+            })
+            .collect();
+        let mut debugger = Debugger::new(Cpu::new(commands.clone()));
+
+        let at_20 = debugger.add_breakpoint(Breakpoint::Cycle(20));
+        let at_pc = debugger.add_breakpoint(Breakpoint::Instruction(100));
+        let x_low = debugger.add_breakpoint(Breakpoint::Register(Register::X, Comparison::Lt, 0));
+        debugger.watch(Register::X);
+
+        // X during cycle 20 is 21 in the example.
+        assert_eq!(debugger.resume(), Stop::Breakpoint(at_20));
+        assert_eq!(debugger.cpu().cycle(), 19);
+        assert_eq!(debugger.watches(), vec![(Register::X, 21)]);
+        assert_eq!(debugger.step(), Stop::Stepped);
+        assert_eq!(debugger.screen()[0].len(), 20);
+
+        assert_eq!(debugger.resume(), Stop::Breakpoint(at_pc));
+        assert_eq!(debugger.cpu().pc(), 100);
+        assert!(debugger.cpu().in_flight().is_none());
+
+        // X first drops below zero at the end of cycle 209.
+        assert_eq!(debugger.resume(), Stop::Breakpoint(x_low));
+        assert_eq!(debugger.cpu().cycle(), 209);
+        assert_eq!(
+            debugger.remove_breakpoint(x_low),
+            Some(Breakpoint::Register(Register::X, Comparison::Lt, 0))
+        );
+        debugger.remove_breakpoint(at_pc);
+        assert_eq!(debugger.resume(), Stop::Halted);
+        assert_eq!(debugger.crt(), render_image(&commands));
+
+        // Breakpoints on the very first cycle and instruction fire before anything runs,
+        // and two at the same place are reported in turn.
+        let mut debugger = Debugger::new(Cpu::new(commands.clone()));
+        let first_cycle = debugger.add_breakpoint(Breakpoint::Cycle(1));
+        let first_pc = debugger.add_breakpoint(Breakpoint::Instruction(0));
+        assert_eq!(debugger.resume(), Stop::Breakpoint(first_cycle));
+        assert_eq!(debugger.resume(), Stop::Breakpoint(first_pc));
+        assert_eq!(debugger.cpu().cycle(), 0);
+
+        // A breakpoint on the next cycle, added while paused, is not skipped.
+        debugger.step();
+        let next = debugger.add_breakpoint(Breakpoint::Cycle(2));
+        assert_eq!(debugger.resume(), Stop::Breakpoint(next));
+        assert_eq!(debugger.cpu().cycle(), 1);
+        assert_eq!(debugger.resume(), Stop::Halted);
+    }
+
+    #[test]
+    pub fn day10_debugger_repl() {
+        let program = assemble("addx 15\naddx -11\nnoop\naddx 6").unwrap();
+        let mut debugger = Debugger::new(Cpu::new(program));
+        let session =
+            "break x > 12\nwatch x\ncontinue\nstep 3\ns\nregs\nbogus\nc\ncrt\nquit\nstep\n";
+        let mut output = vec![];
+        debugger.repl(session.as_bytes(), &mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+
+        assert!(output.contains("Breakpoint 0: x > 12"));
+        assert!(output.contains("Hit breakpoint 0: x > 12\ncycle 2 pc 1 x=16"));
+        assert!(output.contains("cycle 5 pc 3 x=5"));
+        assert!(output.contains("cycle 6 pc 3 (addx 6, 1 cycles left) x=5"));
+        assert!(output.contains("x=5 y=1 z=1 w=1"));
+        assert!(output.contains("Unknown command 'bogus'."));
+        assert!(output.contains("Program halted.\ncycle 7 pc 4 x=11"));
+        assert!(output.contains("##..###\n"));
+        // Nothing after `quit` is run.
+        assert_eq!(debugger.cpu().cycle(), 7);
+    }
+
+    #[test]
+    pub fn day10_crt() {
+        let commands = assemble("addx 3\nnoop\naddx -2\nnoop\nnoop").unwrap();
+
+        // X is 1, 1, 4, 4, 4, 2, 2 during each cycle; the final pixel should be drawn too.
+        let crt = Crt::new(4, 2);
+        let bitmap = crt.render(&commands);
+        assert_eq!(
+            bitmap,
+            vec![
+                vec![true, true, false, true],
+                vec![false, true, true, false]
+            ]
+        );
+        assert_eq!(crt.to_text(&bitmap), "##.#\n.##.\n");
+
+        // A single pixel sprite, with other characters, on a taller screen than needed.
+        let crt = Crt {
+            sprite_width: 1,
+            lit: 'X',
+            dark: ' ',
+            ..Crt::new(4, 3)
+        };
+        assert_eq!(crt.to_text(&crt.render(&commands)), " X  \n  X \n    \n");
+
+        // An even-width sprite covers one more pixel on the right.
+        let crt = Crt {
+            sprite_width: 4,
+            ..Crt::new(8, 1)
+        };
+        assert_eq!(crt.to_text(&crt.render(&commands)), "##.##...\n");
+
+        let mut pbm = vec![];
+        image::write_pbm(&Crt::new(4, 2).render(&commands), &mut pbm).unwrap();
+        assert_eq!(
+            String::from_utf8(pbm).unwrap(),
+            "P1\n4 2\n1 1 0 1\n0 1 1 0\n"
+        );
+
+        // A looping program fills the screen and then stops being read.
+        let forever = assemble("loop: noop\njmp loop").unwrap();
+        let crt = Crt::new(5, 2);
+        assert_eq!(crt.to_text(&crt.render(&forever)), "###..\n###..\n");
     }
 }
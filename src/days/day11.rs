@@ -1,12 +1,15 @@
-use regex::Regex;
-use std::collections::VecDeque;
+use serde_derive::Serialize;
+use serde_json as json;
+use std::collections::{HashMap, VecDeque};
 
-const MONKEY_PATTERN: &str = r"Monkey ([0-9]+):\n  Starting items: ([\d, ]+)\n  Operation: ([=\-*/+ \w\d]+)\n  Test: ([ \w\d]+)\n    If true: throw to monkey ([\d]+)\n    If false: throw to monkey ([\d]+)";
+use crate::err::Error;
+use crate::math::gcd;
+use crate::worry::Worry;
 
 #[derive(Debug, Default)]
-pub struct Monkey {
+pub struct Monkey<W: Worry = i64> {
     pub id: usize,
-    pub items: VecDeque<i64>,
+    pub items: VecDeque<W>,
     pub operation: Operation,
     pub test_divisible: i64,
     pub false_target: usize,
@@ -20,230 +23,1333 @@ impl Monkey {
             ..Default::default()
         }
     }
+
+    /// Converts the monkey's items to another worry backend, e.g. `u128` or `BigUint`.
+    pub fn into_worry<U: Worry>(self) -> Option<Monkey<U>> {
+        let items = self
+            .items
+            .iter()
+            .map(|item| U::from_i64(*item))
+            .collect::<Option<VecDeque<U>>>()?;
+        Some(Monkey {
+            id: self.id,
+            items,
+            operation: self.operation,
+            test_divisible: self.test_divisible,
+            false_target: self.false_target,
+            true_target: self.true_target,
+            inspections: self.inspections,
+        })
+    }
 }
 
-#[derive(Debug, Default)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operator {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+}
+
+impl Operator {
+    fn from_char(ch: char) -> Option<Operator> {
+        match ch {
+            '+' => Some(Self::Add),
+            '-' => Some(Self::Sub),
+            '*' => Some(Self::Mul),
+            '/' => Some(Self::Div),
+            '%' => Some(Self::Rem),
+            _ => None,
+        }
+    }
+
+    fn apply<W: Worry>(&self, lhs: &W, rhs: &W) -> Option<W> {
+        match self {
+            Self::Add => lhs.checked_add(rhs),
+            Self::Sub => lhs.checked_sub(rhs),
+            Self::Mul => lhs.checked_mul(rhs),
+            Self::Div => lhs.checked_div(rhs),
+            Self::Rem => lhs.checked_rem(rhs),
+        }
+    }
+}
+
+/// An arithmetic expression over the old worry level, e.g. `new = (old * 3 + 2) % 7`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
 pub enum Operation {
-    AddNum(i64),
-    MulNum(i64),
     #[default]
-    AddOld,
-    MulOld,
+    Old,
+    Num(i64),
+    Binary(Box<Operation>, Operator, Box<Operation>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Token {
+    Old,
+    Num(i64),
+    Op(Operator),
+    Open,
+    Close,
 }
 
 impl Operation {
-    pub fn from_string(input: &str) -> Operation {
-        let segs: VecDeque<&str> = input.split(" ").collect();
-        let operator = segs[3];
-        let operand = segs[4];
+    /// Parses an expression, with or without the leading `new =`. Errors are located by
+    /// their column within `input`.
+    pub fn from_string(input: &str) -> Result<Operation, Error> {
+        let (offset, expr) = match input.find('=') {
+            Some(eq) if input[..eq].trim() == "new" => (eq + 1, &input[eq + 1..]),
+            Some(_) => return Err(expression_error(1, "Expected 'new' before '='. ")),
+            None => (0, input),
+        };
 
-        if operand == "old" {
-            if operator == "+" {
-                Operation::AddOld
-            } else {
-                Operation::MulOld
-            }
-        } else {
-            let val = operand
-                .parse::<i64>()
-                .expect("The operator should be a number. ");
-            if operator == "+" {
-                Operation::AddNum(val)
-            } else {
-                Operation::MulNum(val)
+        let mut parser = ExpressionParser {
+            tokens: tokenise_expression(expr, offset)?,
+            pos: 0,
+            end: input.len() + 1,
+        };
+        let operation = parser.expression()?;
+        match parser.tokens.get(parser.pos) {
+            None => Ok(operation),
+            Some((column, _)) => Err(expression_error(*column, "Unexpected trailing input. ")),
+        }
+    }
+
+    /// Whether the operation uses only `+` and `*`, so that applying it to a worry level
+    /// reduced modulo some `m` gives the same result, modulo `m`, as the exact level.
+    pub fn respects_modulo(&self) -> bool {
+        match self {
+            Self::Old | Self::Num(_) => true,
+            Self::Binary(lhs, op, rhs) => {
+                matches!(op, Operator::Add | Operator::Mul)
+                    && lhs.respects_modulo()
+                    && rhs.respects_modulo()
             }
         }
     }
 
-    pub fn apply(&self, item_val: i64) -> i64 {
+    /// Applies the operation, returning `None` if the result overflows the worry backend.
+    pub fn apply<W: Worry>(&self, item_val: &W) -> Option<W> {
         match self {
-            Self::AddOld => item_val + item_val,
-            Self::MulOld => item_val * item_val,
-            Self::AddNum(ref num) => item_val + num,
-            Self::MulNum(ref num) => item_val * num,
-        }
-    }
-}
-
-/// A very basic function which parses the required information for this task
-/// from the file into a usable data strcuture.
-pub fn monkeys_from_string(string: &String) -> Vec<Monkey> {
-    // First, get the items using regex.
-    let items_regex = Regex::new(MONKEY_PATTERN).unwrap();
-    items_regex
-        .captures_iter(string)
-        .into_iter()
-        .map(|cap| {
-            let id = cap[1].parse::<usize>().expect("No ID for monkey. ");
-            let items: VecDeque<i64> = cap[2]
-                .split(",")
-                .map(|item| {
-                    item.trim()
-                        .parse::<i64>()
-                        .expect("Unable to parse int from item. ")
+            Self::Old => Some(item_val.clone()),
+            Self::Num(num) => W::from_i64(*num),
+            Self::Binary(lhs, op, rhs) => op.apply(&lhs.apply(item_val)?, &rhs.apply(item_val)?),
+        }
+    }
+}
+
+fn expression_error(column: usize, message: &str) -> Error {
+    Error::ParseError {
+        line: 1,
+        column,
+        message: String::from(message),
+    }
+}
+
+/// Splits an expression into tokens, each paired with its 1-based column, where the
+/// expression starts `offset` characters into the line.
+fn tokenise_expression(input: &str, offset: usize) -> Result<Vec<(usize, Token)>, Error> {
+    let mut tokens = vec![];
+    let mut chars = input.char_indices().peekable();
+    while let Some((i, ch)) = chars.next() {
+        let column = offset + i + 1;
+        match ch {
+            ' ' | '\t' => {}
+            '(' => tokens.push((column, Token::Open)),
+            ')' => tokens.push((column, Token::Close)),
+            '0'..='9' => {
+                let mut end = i + 1;
+                while let Some((j, '0'..='9')) = chars.peek() {
+                    end = j + 1;
+                    chars.next();
+                }
+                let num = input[i..end].parse::<i64>().map_err(|_| {
+                    expression_error(column, &format!("Invalid number '{}'. ", &input[i..end]))
+                })?;
+                tokens.push((column, Token::Num(num)));
+            }
+            'a'..='z' => {
+                let mut end = i + 1;
+                while let Some((j, 'a'..='z')) = chars.peek() {
+                    end = j + 1;
+                    chars.next();
+                }
+                match &input[i..end] {
+                    "old" => tokens.push((column, Token::Old)),
+                    word => {
+                        return Err(expression_error(
+                            column,
+                            &format!("Unknown identifier '{}'. ", word),
+                        ))
+                    }
+                }
+            }
+            _ => match Operator::from_char(ch) {
+                Some(op) => tokens.push((column, Token::Op(op))),
+                None => {
+                    return Err(expression_error(
+                        column,
+                        &format!("Unknown operator '{}'. ", ch),
+                    ))
+                }
+            },
+        }
+    }
+    Ok(tokens)
+}
+
+/// A recursive-descent parser, where `*`, `/` and `%` bind more tightly than `+` and `-`.
+struct ExpressionParser {
+    tokens: Vec<(usize, Token)>,
+    pos: usize,
+    end: usize,
+}
+
+impl ExpressionParser {
+    fn peek(&self) -> Option<Token> {
+        self.tokens.get(self.pos).map(|(_, token)| *token)
+    }
+
+    fn column(&self) -> usize {
+        self.tokens
+            .get(self.pos)
+            .map(|(column, _)| *column)
+            .unwrap_or(self.end)
+    }
+
+    fn expression(&mut self) -> Result<Operation, Error> {
+        let mut lhs = self.term()?;
+        while let Some(Token::Op(op @ (Operator::Add | Operator::Sub))) = self.peek() {
+            self.pos += 1;
+            lhs = Operation::Binary(Box::new(lhs), op, Box::new(self.term()?));
+        }
+        Ok(lhs)
+    }
+
+    fn term(&mut self) -> Result<Operation, Error> {
+        let mut lhs = self.atom()?;
+        while let Some(Token::Op(op @ (Operator::Mul | Operator::Div | Operator::Rem))) =
+            self.peek()
+        {
+            self.pos += 1;
+            lhs = Operation::Binary(Box::new(lhs), op, Box::new(self.atom()?));
+        }
+        Ok(lhs)
+    }
+
+    fn atom(&mut self) -> Result<Operation, Error> {
+        let column = self.column();
+        let token = self.peek();
+        self.pos += 1;
+        match token {
+            Some(Token::Old) => Ok(Operation::Old),
+            Some(Token::Num(num)) => Ok(Operation::Num(num)),
+            Some(Token::Open) => {
+                let inner = self.expression()?;
+                match self.peek() {
+                    Some(Token::Close) => {
+                        self.pos += 1;
+                        Ok(inner)
+                    }
+                    _ => Err(expression_error(self.column(), "Expected ')'. ")),
+                }
+            }
+            Some(_) => Err(expression_error(
+                column,
+                "Expected 'old', a number or '('. ",
+            )),
+            None => Err(expression_error(column, "Unexpected end of expression. ")),
+        }
+    }
+}
+
+/// The fields of a single `Monkey <id>:` block, each paired with the line it was read from.
+#[derive(Debug, Default)]
+struct MonkeyBlock {
+    id: usize,
+    line: usize,
+    items: Option<(usize, VecDeque<i64>)>,
+    operation: Option<(usize, Operation)>,
+    test_divisible: Option<(usize, i64)>,
+    true_target: Option<(usize, usize)>,
+    false_target: Option<(usize, usize)>,
+}
+
+impl MonkeyBlock {
+    fn error(&self, line: usize, message: String) -> Error {
+        Error::BlockError {
+            block: format!("Monkey {}", self.id),
+            line,
+            message,
+        }
+    }
+
+    /// Parses a `<field>: <value>` line into the block.
+    fn add_field(&mut self, raw_line: &str, line: usize) -> Result<(), Error> {
+        let (key, value) = raw_line.split_once(':').ok_or_else(|| {
+            self.error(
+                line,
+                format!("Expected a field, found '{}'. ", raw_line.trim()),
+            )
+        })?;
+        let value_offset = raw_line.len() - value.trim_start().len();
+        let value = value.trim();
+
+        let parse_int = |text: &str, what: &str| {
+            text.trim().parse::<i64>().map_err(|_| {
+                self.error(
+                    line,
+                    format!("Unable to parse {} from '{}'. ", what, text.trim()),
+                )
+            })
+        };
+        let parse_target = |text: &str| {
+            text.strip_prefix("throw to monkey")
+                .and_then(|id| id.trim().parse::<usize>().ok())
+                .ok_or_else(|| {
+                    self.error(
+                        line,
+                        format!("Expected 'throw to monkey <id>', found '{}'. ", text),
+                    )
                 })
-                .collect();
-            let operation = Operation::from_string(&cap[3]);
-            let test_divisible = cap[4].split(" ").nth(2).unwrap().parse::<i64>().unwrap();
-            let true_target = cap[5].parse::<usize>().unwrap();
-            let false_target = cap[6].parse::<usize>().unwrap();
+        };
+
+        let duplicate = match key.trim().to_lowercase().as_str() {
+            "starting items" => {
+                let items = value
+                    .split(',')
+                    .filter(|item| !item.trim().is_empty())
+                    .map(|item| parse_int(item, "an item"))
+                    .collect::<Result<VecDeque<i64>, Error>>()?;
+                self.items.replace((line, items)).is_some()
+            }
+            "operation" => {
+                let operation = Operation::from_string(value).map_err(|err| match err {
+                    Error::ParseError {
+                        column, message, ..
+                    } => self.error(
+                        line,
+                        format!("{}(column {})", message, value_offset + column),
+                    ),
+                    err => err,
+                })?;
+                self.operation.replace((line, operation)).is_some()
+            }
+            "test" => {
+                let divisor = value
+                    .strip_prefix("divisible by")
+                    .ok_or_else(|| {
+                        self.error(
+                            line,
+                            format!("Expected 'divisible by <n>', found '{}'. ", value),
+                        )
+                    })
+                    .and_then(|num| parse_int(num, "a divisor"))?;
+                if divisor <= 0 {
+                    return Err(self.error(line, String::from("The divisor must be positive. ")));
+                }
+                self.test_divisible.replace((line, divisor)).is_some()
+            }
+            "if true" => {
+                let target = parse_target(value)?;
+                self.true_target.replace((line, target)).is_some()
+            }
+            "if false" => {
+                let target = parse_target(value)?;
+                self.false_target.replace((line, target)).is_some()
+            }
+            other => return Err(self.error(line, format!("Unknown field '{}'. ", other))),
+        };
 
-            Monkey {
+        if duplicate {
+            return Err(self.error(line, format!("Duplicate field '{}'. ", key.trim())));
+        }
+        Ok(())
+    }
+
+    fn build(self) -> Result<Monkey, Error> {
+        let missing = |field: &str| self.error(self.line, format!("Missing field '{}'. ", field));
+        let items = self
+            .items
+            .as_ref()
+            .ok_or_else(|| missing("Starting items"))?;
+        let operation = self
+            .operation
+            .as_ref()
+            .ok_or_else(|| missing("Operation"))?;
+        let test_divisible = self.test_divisible.ok_or_else(|| missing("Test"))?;
+        let true_target = self.true_target.ok_or_else(|| missing("If true"))?;
+        let false_target = self.false_target.ok_or_else(|| missing("If false"))?;
+
+        Ok(Monkey {
+            id: self.id,
+            items: items.1.clone(),
+            operation: operation.1.clone(),
+            test_divisible: test_divisible.1,
+            true_target: true_target.1,
+            false_target: false_target.1,
+            ..Default::default()
+        })
+    }
+}
+
+/// Parses the monkey notes into a usable data structure. Each block starts with a
+/// `Monkey <id>:` header, after which the fields may appear in any order, with any
+/// indentation and either LF or CRLF line endings. Monkeys are returned ordered by id, and
+/// ids must run from zero without gaps so that throw targets can index into the result.
+pub fn monkeys_from_string(string: &str) -> Result<Vec<Monkey>, Error> {
+    let mut blocks: Vec<MonkeyBlock> = vec![];
+    for (line_no, raw_line) in string.lines().enumerate() {
+        let line = raw_line.trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        if let Some(header) = line.strip_prefix("Monkey") {
+            let id = header
+                .strip_suffix(':')
+                .and_then(|id| id.trim().parse::<usize>().ok())
+                .ok_or_else(|| Error::ParseError {
+                    line: line_no + 1,
+                    column: raw_line.len() - raw_line.trim_start().len() + 1,
+                    message: format!("Invalid monkey header '{}'. ", line),
+                })?;
+            blocks.push(MonkeyBlock {
                 id,
-                items,
-                operation,
-                test_divisible,
-                true_target,
-                false_target,
+                line: line_no + 1,
                 ..Default::default()
+            });
+            continue;
+        }
+
+        match blocks.last_mut() {
+            Some(block) => block.add_field(raw_line, line_no + 1)?,
+            None => {
+                return Err(Error::ParseError {
+                    line: line_no + 1,
+                    column: 1,
+                    message: String::from("Expected a 'Monkey <id>:' header. "),
+                })
+            }
+        }
+    }
+
+    blocks.sort_by_key(|block| block.id);
+    for (index, block) in blocks.iter().enumerate() {
+        if block.id != index {
+            return Err(block.error(
+                block.line,
+                format!(
+                    "Expected monkey {} here; ids must be unique and contiguous. ",
+                    index
+                ),
+            ));
+        }
+        for (line, target) in [block.true_target, block.false_target]
+            .into_iter()
+            .flatten()
+        {
+            if target >= blocks.len() {
+                return Err(block.error(
+                    line,
+                    format!("Throw target monkey {} does not exist. ", target),
+                ));
             }
+        }
+    }
+
+    blocks.into_iter().map(MonkeyBlock::build).collect()
+}
+
+/// How a monkey's worry level is relieved after each inspection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WorryPolicy {
+    /// Divide the worry level by `k`, as in part 1.
+    DivideBy(i64),
+    /// Keep the worry level modulo the LCM of every monkey's test, as in part 2.
+    ModuloLcm,
+    /// Leave the worry level untouched. This needs a wide backend, such as `BigUint`.
+    None,
+}
+
+impl WorryPolicy {
+    pub fn relieve<W: Worry>(&self, worry: W, lcm: &W) -> Option<W> {
+        match self {
+            Self::DivideBy(k) => worry.checked_div(&W::from_i64(*k)?),
+            Self::ModuloLcm => worry.checked_rem(lcm),
+            Self::None => Some(worry),
+        }
+    }
+}
+
+/// A group of monkeys passing items between each other under a given [`WorryPolicy`].
+#[derive(Debug)]
+pub struct Troop<W: Worry = i64> {
+    monkeys: Vec<Monkey<W>>,
+    policy: WorryPolicy,
+    lcm: i64,
+    rounds: usize,
+    tracer: Option<Tracer<W>>,
+}
+
+impl<W: Worry> Troop<W> {
+    /// Returns an error if a monkey's test is not a positive divisor, or if the LCM of
+    /// the tests overflows an `i64`. Under [`WorryPolicy::ModuloLcm`] every operation must
+    /// also use only `+` and `*`, since other operators do not survive the reduction.
+    pub fn new(monkeys: Vec<Monkey<W>>, policy: WorryPolicy) -> Result<Troop<W>, Error> {
+        let mut lcm_all = 1;
+        for monkey in monkeys.iter() {
+            if monkey.test_divisible <= 0 {
+                return Err(Error::InvalidInput(format!(
+                    "Test for monkey {} must be divisible by a positive number, not {}. ",
+                    monkey.id, monkey.test_divisible
+                )));
+            }
+            if policy == WorryPolicy::ModuloLcm && !monkey.operation.respects_modulo() {
+                return Err(Error::InvalidInput(format!(
+                    "Operation for monkey {} uses '-', '/' or '%', which the modulo-LCM policy \
+                     cannot reduce. ",
+                    monkey.id
+                )));
+            }
+            lcm_all = lcm(lcm_all, monkey.test_divisible).ok_or_else(|| {
+                Error::Overflow(format!(
+                    "LCM of the tests overflows at monkey {}. ",
+                    monkey.id
+                ))
+            })?;
+        }
+        Ok(Troop {
+            monkeys,
+            policy,
+            lcm: lcm_all,
+            rounds: 0,
+            tracer: None,
         })
-        .collect()
+    }
+
+    pub fn monkeys(&self) -> &[Monkey<W>] {
+        &self.monkeys
+    }
+
+    /// The least common multiple of every monkey's divisibility test.
+    pub fn lcm(&self) -> i64 {
+        self.lcm
+    }
+
+    /// The number of rounds run so far.
+    pub fn rounds(&self) -> usize {
+        self.rounds
+    }
+
+    /// Starts recording every throw and a snapshot of the queues after each round. Items
+    /// currently held are numbered in monkey order, starting from zero.
+    pub fn enable_tracing(&mut self) {
+        self.tracer = Some(Tracer::new(&self.monkeys, self.rounds));
+    }
+
+    pub fn tracer(&self) -> Option<&Tracer<W>> {
+        self.tracer.as_ref()
+    }
+
+    fn lcm_worry(&self) -> Result<W, Error> {
+        W::from_i64(self.lcm).ok_or_else(|| {
+            Error::Overflow(format!("LCM {} does not fit the worry backend. ", self.lcm))
+        })
+    }
+
+    /// Has monkey `imonkey` inspect an item, returning the monkey it is thrown to and its
+    /// new worry level.
+    fn inspect(&self, imonkey: usize, item: &W, lcm: &W) -> Result<(usize, W), Error> {
+        let monkey = &self.monkeys[imonkey];
+        let worry = monkey
+            .operation
+            .apply(item)
+            .and_then(|worry| self.policy.relieve(worry, lcm))
+            .ok_or_else(|| {
+                Error::Overflow(format!(
+                    "Monkey {} overflowed inspecting an item with worry level {}. ",
+                    monkey.id, item
+                ))
+            })?;
+        let divisor = W::from_i64(monkey.test_divisible).ok_or_else(|| {
+            Error::Overflow(format!(
+                "Test for monkey {} does not fit the worry backend. ",
+                monkey.id
+            ))
+        })?;
+        let rem = worry.checked_rem(&divisor).ok_or_else(|| {
+            Error::Overflow(format!(
+                "Monkey {} could not test an item with worry level {}. ",
+                monkey.id, worry
+            ))
+        })?;
+        let itar = if rem.is_zero() {
+            monkey.true_target
+        } else {
+            monkey.false_target
+        };
+        Ok((itar, worry))
+    }
+
+    /// Runs a single round, in which each monkey in turn inspects and throws all of its items.
+    /// Returns an error if a worry level overflows the backend.
+    pub fn round(&mut self) -> Result<(), Error> {
+        let lcm = self.lcm_worry()?;
+
+        for imonkey in 0..self.monkeys.len() {
+            let items: Vec<W> = self.monkeys[imonkey].items.drain(..).collect();
+            self.monkeys[imonkey].inspections += items.len();
+            let item_ids: Vec<usize> = match self.tracer.as_mut() {
+                Some(tracer) => tracer.queues[imonkey].drain(..).collect(),
+                None => vec![],
+            };
+
+            for (iitem, item) in items.into_iter().enumerate() {
+                let (itar, worry) = self.inspect(imonkey, &item, &lcm)?;
+
+                if let Some(tracer) = self.tracer.as_mut() {
+                    tracer.queues[itar].push_back(item_ids[iitem]);
+                    tracer.throws.push(Throw {
+                        round: self.rounds + 1,
+                        item: item_ids[iitem],
+                        from: imonkey,
+                        to: itar,
+                        worry_before: item,
+                        worry_after: worry.clone(),
+                    });
+                }
+                self.monkeys[itar].items.push_back(worry);
+            }
+        }
+
+        self.rounds += 1;
+        if let Some(tracer) = self.tracer.as_mut() {
+            tracer.snapshot(&self.monkeys, self.rounds);
+        }
+        Ok(())
+    }
+
+    /// Follows a single item, held by `imonkey` at the start of a round, until the end of
+    /// that round, adding its inspections to `counts`. An item thrown to a later monkey is
+    /// inspected again in the same round, while one thrown to an earlier monkey waits.
+    fn item_round(
+        &self,
+        mut imonkey: usize,
+        mut worry: W,
+        lcm: &W,
+        counts: &mut [u64],
+    ) -> Result<(usize, W), Error> {
+        loop {
+            counts[imonkey] += 1;
+            let (itar, new_worry) = self.inspect(imonkey, &worry, lcm)?;
+            worry = new_worry;
+            if itar <= imonkey {
+                return Ok((itar, worry));
+            }
+            imonkey = itar;
+        }
+    }
+
+    /// Counts each monkey's inspections after `n` more rounds, without running them.
+    ///
+    /// Each item's journey depends only on its own worry level, so items are followed one
+    /// at a time until their `(monkey, worry)` state at the start of a round repeats. The
+    /// counts for the remaining rounds are then extrapolated from the cycle. Under
+    /// [`WorryPolicy::ModuloLcm`] there are finitely many states, so this takes bounded time
+    /// however large `n` is. Under other policies an item may never cycle, so they are an
+    /// error; use [`Troop::run`] instead.
+    pub fn fast_forward_inspections(&self, n: u64) -> Result<Vec<u64>, Error> {
+        if self.policy != WorryPolicy::ModuloLcm {
+            return Err(Error::InvalidInput(format!(
+                "Items may never repeat a state under {:?}, so they cannot be fast-forwarded. ",
+                self.policy
+            )));
+        }
+        let lcm = self.lcm_worry()?;
+        let mut totals: Vec<u64> = self.monkeys.iter().map(|m| m.inspections as u64).collect();
+
+        for (istart, monkey) in self.monkeys.iter().enumerate() {
+            for item in monkey.items.iter() {
+                let counts = self.fast_forward_item(istart, item.clone(), &lcm, n)?;
+                for (total, count) in totals.iter_mut().zip(counts) {
+                    *total += count;
+                }
+            }
+        }
+        Ok(totals)
+    }
+
+    fn fast_forward_item(
+        &self,
+        imonkey: usize,
+        worry: W,
+        lcm: &W,
+        n: u64,
+    ) -> Result<Vec<u64>, Error> {
+        let mut state = (imonkey, worry);
+        let mut seen: HashMap<(usize, W), usize> = HashMap::from([(state.clone(), 0)]);
+        // The inspections by each monkey over the first `r` rounds, indexed by `r`.
+        let mut cumulative: Vec<Vec<u64>> = vec![vec![0; self.monkeys.len()]];
+
+        for round in 1..=n {
+            let mut counts = cumulative.last().unwrap().clone();
+            state = self.item_round(state.0, state.1, lcm, &mut counts)?;
+            cumulative.push(counts);
+
+            let r = cumulative.len() - 1;
+            if round == n {
+                break;
+            }
+            if let Some(&start) = seen.get(&state) {
+                let period = (r - start) as u64;
+                let remaining = n - round;
+                let (cycles, rem) = (remaining / period, (remaining % period) as usize);
+                return Ok((0..self.monkeys.len())
+                    .map(|k| {
+                        let per_cycle = cumulative[r][k] - cumulative[start][k];
+                        let partial = cumulative[start + rem][k] - cumulative[start][k];
+                        cumulative[r][k] + cycles * per_cycle + partial
+                    })
+                    .collect());
+            }
+            seen.insert(state.clone(), r);
+        }
+        Ok(cumulative.pop().unwrap())
+    }
+
+    pub fn run(&mut self, n: usize) -> Result<(), Error> {
+        for _ in 0..n {
+            self.round()?;
+        }
+        Ok(())
+    }
+
+    /// The product of the inspection counts of the `top_k` most active monkeys, or an error
+    /// if it overflows a `usize`.
+    pub fn monkey_business(&self, top_k: usize) -> Result<usize, Error> {
+        let mut inspections: Vec<usize> = self.monkeys.iter().map(|m| m.inspections).collect();
+        inspections.sort_unstable_by(|a, b| b.cmp(a));
+        inspections
+            .into_iter()
+            .take(top_k)
+            .try_fold(1_usize, |acc, count| acc.checked_mul(count))
+            .ok_or_else(|| {
+                Error::Overflow(format!(
+                    "Monkey business of the top {} monkeys overflows a usize. ",
+                    top_k
+                ))
+            })
+    }
+
+    /// The monkey business after `n` more rounds, computed by [`Troop::fast_forward_inspections`].
+    /// This is a `u128`, as the product of two `u64` counts need not fit a `u64`.
+    pub fn fast_forward_monkey_business(&self, n: u64, top_k: usize) -> Result<u128, Error> {
+        let mut inspections = self.fast_forward_inspections(n)?;
+        inspections.sort_unstable_by(|a, b| b.cmp(a));
+        inspections
+            .into_iter()
+            .take(top_k)
+            .try_fold(1_u128, |acc, count| acc.checked_mul(count as u128))
+            .ok_or_else(|| {
+                Error::Overflow(format!(
+                    "Monkey business of the top {} monkeys overflows a u128. ",
+                    top_k
+                ))
+            })
+    }
+}
+
+/// A single item being thrown from one monkey to another.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Throw<W: Worry> {
+    pub round: usize,
+    pub item: usize,
+    pub from: usize,
+    pub to: usize,
+    /// The worry level before the monkey inspected the item.
+    pub worry_before: W,
+    /// The worry level after inspection and relief, as received by `to`.
+    pub worry_after: W,
+}
+
+/// The contents of every monkey's queue at the end of a round.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Snapshot<W: Worry> {
+    pub round: usize,
+    /// The `(item, worry)` pairs held by each monkey, in queue order.
+    pub queues: Vec<Vec<(usize, W)>>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct MonkeyStats {
+    pub monkey: usize,
+    pub inspections: usize,
+    pub received: usize,
+    /// The number of throws made to each monkey, indexed by target.
+    pub thrown_to: Vec<usize>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct ItemStats {
+    pub item: usize,
+    pub inspections: usize,
+    /// The number of times the item was inspected by each monkey.
+    pub inspected_by: Vec<usize>,
+    /// The monkey holding the item at the end of the trace.
+    pub holder: usize,
+}
+
+/// Records the flow of items between monkeys, for analysis and export.
+#[derive(Debug, Clone)]
+pub struct Tracer<W: Worry> {
+    throws: Vec<Throw<W>>,
+    snapshots: Vec<Snapshot<W>>,
+    /// The item ids held by each monkey, mirroring the monkeys' item queues.
+    queues: Vec<VecDeque<usize>>,
+}
+
+impl<W: Worry> Tracer<W> {
+    fn new(monkeys: &[Monkey<W>], round: usize) -> Tracer<W> {
+        let mut next_id = 0;
+        let queues = monkeys
+            .iter()
+            .map(|monkey| {
+                next_id += monkey.items.len();
+                (next_id - monkey.items.len()..next_id).collect()
+            })
+            .collect();
+        let mut tracer = Tracer {
+            throws: vec![],
+            snapshots: vec![],
+            queues,
+        };
+        tracer.snapshot(monkeys, round);
+        tracer
+    }
+
+    fn snapshot(&mut self, monkeys: &[Monkey<W>], round: usize) {
+        let queues = monkeys
+            .iter()
+            .zip(self.queues.iter())
+            .map(|(monkey, ids)| {
+                ids.iter()
+                    .cloned()
+                    .zip(monkey.items.iter().cloned())
+                    .collect()
+            })
+            .collect();
+        self.snapshots.push(Snapshot { round, queues });
+    }
+
+    pub fn throws(&self) -> &[Throw<W>] {
+        &self.throws
+    }
+
+    /// One snapshot per round, starting with the state when tracing was enabled.
+    pub fn snapshots(&self) -> &[Snapshot<W>] {
+        &self.snapshots
+    }
+
+    pub fn n_items(&self) -> usize {
+        self.queues.iter().map(|queue| queue.len()).sum()
+    }
+
+    pub fn monkey_stats(&self) -> Vec<MonkeyStats> {
+        let n = self.queues.len();
+        let mut stats: Vec<MonkeyStats> = (0..n)
+            .map(|monkey| MonkeyStats {
+                monkey,
+                thrown_to: vec![0; n],
+                ..Default::default()
+            })
+            .collect();
+        for throw in self.throws.iter() {
+            stats[throw.from].inspections += 1;
+            stats[throw.from].thrown_to[throw.to] += 1;
+            stats[throw.to].received += 1;
+        }
+        stats
+    }
+
+    pub fn item_stats(&self) -> Vec<ItemStats> {
+        let mut stats: Vec<ItemStats> = (0..self.n_items())
+            .map(|item| ItemStats {
+                item,
+                inspected_by: vec![0; self.queues.len()],
+                ..Default::default()
+            })
+            .collect();
+        for throw in self.throws.iter() {
+            stats[throw.item].inspections += 1;
+            stats[throw.item].inspected_by[throw.from] += 1;
+        }
+        for (monkey, queue) in self.queues.iter().enumerate() {
+            for item in queue.iter() {
+                stats[*item].holder = monkey;
+            }
+        }
+        stats
+    }
+
+    /// Every throw as CSV, with a header row.
+    pub fn throws_csv(&self) -> String {
+        let mut csv = String::from("round,item,from,to,worry_before,worry_after\n");
+        for throw in self.throws.iter() {
+            csv += &format!(
+                "{},{},{},{},{},{}\n",
+                throw.round,
+                throw.item,
+                throw.from,
+                throw.to,
+                throw.worry_before,
+                throw.worry_after
+            );
+        }
+        csv
+    }
+
+    /// Every snapshot as CSV, with one row per item held at the end of each round.
+    pub fn snapshots_csv(&self) -> String {
+        let mut csv = String::from("round,monkey,position,item,worry\n");
+        for snapshot in self.snapshots.iter() {
+            for (monkey, queue) in snapshot.queues.iter().enumerate() {
+                for (position, (item, worry)) in queue.iter().enumerate() {
+                    csv += &format!(
+                        "{},{},{},{},{}\n",
+                        snapshot.round, monkey, position, item, worry
+                    );
+                }
+            }
+        }
+        csv
+    }
+
+    /// The whole trace, with its summary statistics, as JSON. Worry levels that do not fit
+    /// in an `i64` are written as strings.
+    pub fn to_json(&self) -> json::Value {
+        let worry_json = |worry: &W| {
+            let text = worry.to_string();
+            text.parse::<i64>()
+                .map(json::Value::from)
+                .unwrap_or(json::Value::String(text))
+        };
+
+        json::json!({
+            "throws": self.throws.iter().map(|throw| json::json!({
+                "round": throw.round,
+                "item": throw.item,
+                "from": throw.from,
+                "to": throw.to,
+                "worry_before": worry_json(&throw.worry_before),
+                "worry_after": worry_json(&throw.worry_after),
+            })).collect::<Vec<json::Value>>(),
+            "snapshots": self.snapshots.iter().map(|snapshot| json::json!({
+                "round": snapshot.round,
+                "queues": snapshot.queues.iter().map(|queue| {
+                    queue.iter().map(|(item, worry)| json::json!({
+                        "item": item,
+                        "worry": worry_json(worry),
+                    })).collect::<Vec<json::Value>>()
+                }).collect::<Vec<Vec<json::Value>>>(),
+            })).collect::<Vec<json::Value>>(),
+            "monkey_stats": self.monkey_stats(),
+            "item_stats": self.item_stats(),
+        })
+    }
+}
+
+fn lcm(a: i64, b: i64) -> Option<i64> {
+    (a / gcd(a, b)).checked_mul(b)
 }
 
 #[cfg(test)]
 mod tests {
-    use super::monkeys_from_string;
+    use super::{monkeys_from_string, Monkey, Operation, Operator, Throw, Troop, WorryPolicy};
+    use crate::err::Error;
     use crate::io;
+    use crate::worry::BigUint;
     use std::path::Path;
 
     #[test]
     pub fn day11_example() {
         let input_string =
             io::read_string(Path::new("data/day11/example.txt")).expect("Unable to find input. ");
-        let mut monkies = monkeys_from_string(&input_string);
-
-        for _ in 0..20 {
-            for imonkey in 0..monkies.len() {
-                // First, do a pass over to test the monkies.
-                let mut inspec = 0;
-                monkies[imonkey].items = monkies[imonkey]
-                    .items
-                    .iter()
-                    .map(|item| {
-                        inspec += 1;
-                        monkies[imonkey].operation.apply(*item) / 3
-                    })
-                    .collect();
-
-                monkies[imonkey].inspections += inspec;
-
-                // Now check the items
-                while let Some(item) = monkies[imonkey].items.pop_front() {
-                    if item % monkies[imonkey].test_divisible == 0 {
-                        let itar = monkies[imonkey].true_target;
-                        monkies[itar].items.push_back(item);
-                    } else {
-                        let itar = monkies[imonkey].false_target;
-                        monkies[itar].items.push_back(item);
-                    }
-                }
-            }
-        }
+        let mut troop = Troop::new(
+            monkeys_from_string(&input_string).unwrap(),
+            WorryPolicy::DivideBy(3),
+        )
+        .unwrap();
+        troop.run(20).unwrap();
 
-        let mut inspections: Vec<usize> = monkies.iter().map(|monk| monk.inspections).collect();
-        inspections.sort();
-        let top2_inspections = inspections
-            .into_iter()
-            .rev()
-            .take(2)
-            .collect::<Vec<usize>>();
-        assert_eq!(top2_inspections[0] * top2_inspections[1], 10605);
+        let inspections: Vec<usize> = troop.monkeys().iter().map(|m| m.inspections).collect();
+        assert_eq!(inspections, vec![101, 95, 7, 105]);
+        assert_eq!(troop.monkey_business(2).unwrap(), 10605);
+    }
+
+    #[test]
+    pub fn day11_example_part2() {
+        let input_string =
+            io::read_string(Path::new("data/day11/example.txt")).expect("Unable to find input. ");
+        let mut troop = Troop::new(
+            monkeys_from_string(&input_string).unwrap(),
+            WorryPolicy::ModuloLcm,
+        )
+        .unwrap();
+        troop.run(10_000).unwrap();
+        assert_eq!(troop.monkey_business(2).unwrap(), 2713310158);
     }
 
     #[test]
     pub fn day11_part1() {
         let input_string =
             io::read_string(Path::new("data/day11/data.txt")).expect("Unable to find input. ");
-        let mut monkies = monkeys_from_string(&input_string);
-
-        for _ in 0..20 {
-            for imonkey in 0..monkies.len() {
-                // First, do a pass over to test the monkies.
-                let mut inspec = 0;
-                monkies[imonkey].items = monkies[imonkey]
-                    .items
-                    .iter()
-                    .map(|item| {
-                        inspec += 1;
-                        monkies[imonkey].operation.apply(*item) / 3
-                    })
-                    .collect();
-
-                monkies[imonkey].inspections += inspec;
-
-                // Now check the items
-                while let Some(item) = monkies[imonkey].items.pop_front() {
-                    if item % monkies[imonkey].test_divisible == 0 {
-                        let itar = monkies[imonkey].true_target;
-                        monkies[itar].items.push_back(item);
-                    } else {
-                        let itar = monkies[imonkey].false_target;
-                        monkies[itar].items.push_back(item);
-                    }
-                }
-            }
-        }
+        let mut troop = Troop::new(
+            monkeys_from_string(&input_string).unwrap(),
+            WorryPolicy::DivideBy(3),
+        )
+        .unwrap();
+        troop.run(20).unwrap();
 
-        let mut inspections: Vec<usize> = monkies.iter().map(|monk| monk.inspections).collect();
-        inspections.sort();
-        let top2_inspections = inspections
-            .into_iter()
-            .rev()
-            .take(2)
-            .collect::<Vec<usize>>();
         // The answer provided by AOC.
-        assert_eq!(top2_inspections[0] * top2_inspections[1], 51075);
+        assert_eq!(troop.monkey_business(2).unwrap(), 51075);
     }
 
     #[test]
     pub fn day11_part2() {
         let input_string =
             io::read_string(Path::new("data/day11/data.txt")).expect("Unable to find input. ");
-        let mut monkies = monkeys_from_string(&input_string);
-
-        // For this solution, we are going to use the least common multiple for this.
-        // When we apply the operation, we divide by the product of moduli to ensure that we don't
-        // while still getting the correct modulus from the tests.
-        let modproduct: i64 = monkies.iter().map(|m| m.test_divisible).product();
-        println!("{}", modproduct);
-
-        for _ in 0..10_000 {
-            for imonkey in 0..monkies.len() {
-                // First, do a pass over to test the monkies.
-                let mut inspec = 0;
-                monkies[imonkey].items = monkies[imonkey]
-                    .items
-                    .iter()
-                    .map(|item| {
-                        inspec += 1;
-                        monkies[imonkey].operation.apply(*item % modproduct)
-                    })
-                    .collect();
-
-                monkies[imonkey].inspections += inspec;
-
-                // Now check the items
-                while let Some(item) = monkies[imonkey].items.pop_front() {
-                    if item % monkies[imonkey].test_divisible == 0 {
-                        let itar = monkies[imonkey].true_target;
-                        monkies[itar].items.push_back(item);
-                    } else {
-                        let itar = monkies[imonkey].false_target;
-                        monkies[itar].items.push_back(item);
-                    }
-                }
+
+        // For this solution, we keep each worry level modulo the least common multiple of the
+        // monkeys' tests, which keeps the numbers small while still giving the correct result
+        // for every divisibility test.
+        let mut troop = Troop::new(
+            monkeys_from_string(&input_string).unwrap(),
+            WorryPolicy::ModuloLcm,
+        )
+        .unwrap();
+        troop.run(10_000).unwrap();
+
+        // The answer provided by AOC.
+        assert_eq!(troop.monkey_business(2).unwrap(), 11741456163);
+        assert!(matches!(troop.monkey_business(8), Err(Error::Overflow(_))));
+    }
+
+    #[test]
+    pub fn day11_exact_worry() {
+        let input_string =
+            io::read_string(Path::new("data/day11/example.txt")).expect("Unable to find input. ");
+
+        // Without any relief, squaring soon overflows an i64, which should be reported.
+        let mut troop = Troop::new(
+            monkeys_from_string(&input_string).unwrap(),
+            WorryPolicy::None,
+        )
+        .unwrap();
+        assert!(matches!(troop.run(20), Err(Error::Overflow(_))));
+
+        // An exact simulation with big integers should agree with the modulo-LCM trick.
+        let monkeys: Vec<Monkey<BigUint>> = monkeys_from_string(&input_string)
+            .unwrap()
+            .into_iter()
+            .map(|m| m.into_worry().unwrap())
+            .collect();
+        let mut exact = Troop::new(monkeys, WorryPolicy::None).unwrap();
+        exact.run(8).unwrap();
+
+        let mut modulo = Troop::new(
+            monkeys_from_string(&input_string).unwrap(),
+            WorryPolicy::ModuloLcm,
+        )
+        .unwrap();
+        modulo.run(8).unwrap();
+
+        for (a, b) in exact.monkeys().iter().zip(modulo.monkeys()) {
+            assert_eq!(a.inspections, b.inspections);
+        }
+    }
+
+    #[test]
+    pub fn day11_operation_expressions() {
+        let op = Operation::from_string("new = (old * 3 + 2) % 7").unwrap();
+        assert_eq!(op.apply(&5_i64), Some(3));
+        assert_eq!(op.apply(&BigUint::from_u64(5)), Some(BigUint::from_u64(3)));
+
+        // Multiplication binds more tightly than addition and operators are left-associative.
+        let op = Operation::from_string("old + old * 2 - 10 / 5 / 2").unwrap();
+        assert_eq!(op.apply(&4_i64), Some(11));
+
+        assert_eq!(
+            Operation::from_string("new = old * old").unwrap(),
+            Operation::Binary(
+                Box::new(Operation::Old),
+                Operator::Mul,
+                Box::new(Operation::Old)
+            )
+        );
+        assert_eq!(
+            Operation::from_string("old - 3").unwrap().apply(&2_u64),
+            None
+        );
+    }
+
+    #[test]
+    pub fn day11_operation_errors() {
+        let column_of = |input: &str| match Operation::from_string(input) {
+            Err(Error::ParseError { column, .. }) => column,
+            other => panic!("Expected a parse error, got {:?}", other),
+        };
+        assert_eq!(column_of("new = old ^ 2"), 11);
+        assert_eq!(column_of("new = (old + 2"), 15);
+        assert_eq!(column_of("new = old + older"), 13);
+        assert_eq!(column_of("new = old 2"), 11);
+        assert_eq!(column_of("old = old + 1"), 1);
+    }
+
+    #[test]
+    pub fn day11_parse_tolerant() {
+        // CRLF line endings, extra blank lines, odd indentation and reordered fields.
+        let input_string = [
+            "",
+            "Monkey 1:",
+            "\tOperation: new = old + 6",
+            "  Starting items: 54, 65",
+            "",
+            "  If false: throw to monkey 0",
+            "Test: divisible by 19",
+            "        If true: throw to monkey 0",
+            "Monkey 0:",
+            "  Test: divisible by 3",
+            "  Starting items:",
+            "  If true: throw to monkey 1",
+            "  If false: throw to monkey 1",
+            "  Operation: new = old * old",
+        ]
+        .join("\r\n");
+        let monkeys = monkeys_from_string(&input_string).unwrap();
+
+        assert_eq!(monkeys.len(), 2);
+        assert_eq!(monkeys[0].id, 0);
+        assert!(monkeys[0].items.is_empty());
+        assert_eq!(monkeys[1].items, vec![54, 65]);
+        assert_eq!(
+            monkeys[1].operation,
+            Operation::from_string("old + 6").unwrap()
+        );
+        assert_eq!(monkeys[1].test_divisible, 19);
+        assert_eq!((monkeys[1].true_target, monkeys[1].false_target), (0, 0));
+
+        let input_string =
+            io::read_string(Path::new("data/day11/example.txt")).expect("Unable to find input. ");
+        let crlf = monkeys_from_string(&input_string.replace('\n', "\r\n")).unwrap();
+        let mut troop = Troop::new(crlf, WorryPolicy::DivideBy(3)).unwrap();
+        troop.run(20).unwrap();
+        assert_eq!(troop.monkey_business(2).unwrap(), 10605);
+    }
+
+    #[test]
+    pub fn day11_parse_errors() {
+        let input_string =
+            io::read_string(Path::new("data/day11/example.txt")).expect("Unable to find input. ");
+        let block_of = |input: &str| match monkeys_from_string(input) {
+            Err(Error::BlockError { block, line, .. }) => (block, line),
+            other => panic!("Expected a block error, got {:?}", other),
+        };
+
+        assert_eq!(
+            block_of(&input_string.replace("throw to monkey 3", "throw to monkey 7")),
+            (String::from("Monkey 0"), 6)
+        );
+        assert_eq!(
+            block_of(&input_string.replace("new = old + 6", "new = old ^ 6")),
+            (String::from("Monkey 1"), 10)
+        );
+        assert_eq!(
+            block_of(&input_string.replace("  Test: divisible by 13\n", "")),
+            (String::from("Monkey 2"), 15)
+        );
+        assert_eq!(
+            block_of(&input_string.replace("Monkey 3:", "Monkey 2:")),
+            (String::from("Monkey 2"), 22)
+        );
+    }
+
+    #[test]
+    pub fn day11_troop_validation() {
+        let input_string =
+            io::read_string(Path::new("data/day11/example.txt")).expect("Unable to find input. ");
+
+        let mut monkeys = monkeys_from_string(&input_string).unwrap();
+        monkeys[2].test_divisible = 0;
+        assert!(matches!(
+            Troop::new(monkeys, WorryPolicy::ModuloLcm),
+            Err(Error::InvalidInput(_))
+        ));
+
+        // Large coprime tests push the LCM past an i64.
+        let mut monkeys = monkeys_from_string(&input_string).unwrap();
+        for (monkey, prime) in monkeys
+            .iter_mut()
+            .zip([1_000_003, 1_000_033, 1_000_037, 1_000_039])
+        {
+            monkey.test_divisible = prime;
+        }
+        assert!(matches!(
+            Troop::new(monkeys, WorryPolicy::ModuloLcm),
+            Err(Error::Overflow(_))
+        ));
+
+        // Subtraction, division and remainder give wrong answers once worry is reduced, so
+        // they are only allowed when it is not.
+        assert!(Operation::from_string("old * 3 + 2")
+            .unwrap()
+            .respects_modulo());
+        for op in ["old - 3", "old / 2 + 1", "(old + 2) % 7"] {
+            let mut monkeys = monkeys_from_string(&input_string).unwrap();
+            monkeys[1].operation = Operation::from_string(op).unwrap();
+            assert!(!monkeys[1].operation.respects_modulo());
+            assert!(matches!(
+                Troop::new(monkeys, WorryPolicy::ModuloLcm),
+                Err(Error::InvalidInput(_))
+            ));
+            let mut monkeys = monkeys_from_string(&input_string).unwrap();
+            monkeys[1].operation = Operation::from_string(op).unwrap();
+            assert!(Troop::new(monkeys, WorryPolicy::DivideBy(3)).is_ok());
+        }
+    }
+
+    #[test]
+    pub fn day11_tracing() {
+        let input_string =
+            io::read_string(Path::new("data/day11/example.txt")).expect("Unable to find input. ");
+        let mut troop = Troop::new(
+            monkeys_from_string(&input_string).unwrap(),
+            WorryPolicy::DivideBy(3),
+        )
+        .unwrap();
+        troop.enable_tracing();
+        troop.run(20).unwrap();
+        let tracer = troop.tracer().unwrap();
+
+        // Monkey 0 inspects item 0 (worry 79), and throws 500 to monkey 3.
+        assert_eq!(
+            tracer.throws()[0],
+            Throw {
+                round: 1,
+                item: 0,
+                from: 0,
+                to: 3,
+                worry_before: 79,
+                worry_after: 500,
             }
+        );
+        assert_eq!(tracer.snapshots().len(), 21);
+        let round1: Vec<Vec<i64>> = tracer.snapshots()[1]
+            .queues
+            .iter()
+            .map(|queue| queue.iter().map(|(_, worry)| *worry).collect())
+            .collect();
+        assert_eq!(
+            round1,
+            vec![
+                vec![20, 23, 27, 26],
+                vec![2080, 25, 167, 207, 401, 1046],
+                vec![],
+                vec![]
+            ]
+        );
+
+        let monkey_stats = tracer.monkey_stats();
+        let inspections: Vec<usize> = monkey_stats.iter().map(|s| s.inspections).collect();
+        assert_eq!(inspections, vec![101, 95, 7, 105]);
+        let item_stats = tracer.item_stats();
+        assert_eq!(item_stats.len(), 10);
+        assert_eq!(
+            item_stats.iter().map(|s| s.inspections).sum::<usize>(),
+            inspections.iter().sum::<usize>()
+        );
+
+        assert!(tracer
+            .throws_csv()
+            .starts_with("round,item,from,to,worry_before,worry_after\n1,0,0,3,79,500\n"));
+        assert_eq!(tracer.snapshots_csv().lines().count(), 1 + 21 * 10);
+        let json = tracer.to_json();
+        assert_eq!(json["throws"][0]["worry_after"], 500);
+        assert_eq!(json["monkey_stats"][3]["inspections"], 105);
+    }
+
+    #[test]
+    pub fn day11_fast_forward() {
+        for path in ["data/day11/example.txt", "data/day11/data.txt"] {
+            let input_string = io::read_string(Path::new(path)).expect("Unable to find input. ");
+            let troop = Troop::new(
+                monkeys_from_string(&input_string).unwrap(),
+                WorryPolicy::ModuloLcm,
+            )
+            .unwrap();
+            let fast = troop.fast_forward_inspections(10_000).unwrap();
+
+            let mut direct = Troop::new(
+                monkeys_from_string(&input_string).unwrap(),
+                WorryPolicy::ModuloLcm,
+            )
+            .unwrap();
+            direct.run(10_000).unwrap();
+            let expected: Vec<u64> = direct
+                .monkeys()
+                .iter()
+                .map(|m| m.inspections as u64)
+                .collect();
+            assert_eq!(fast, expected);
+
+            // Fast-forwarding from part way through should agree too.
+            let mut partial = Troop::new(
+                monkeys_from_string(&input_string).unwrap(),
+                WorryPolicy::ModuloLcm,
+            )
+            .unwrap();
+            partial.run(1234).unwrap();
+            assert_eq!(
+                partial.fast_forward_inspections(10_000 - 1234).unwrap(),
+                expected
+            );
         }
 
-        let mut inspections: Vec<usize> = monkies.iter().map(|monk| monk.inspections).collect();
-        inspections.sort();
-        let top2_inspections = inspections
-            .into_iter()
-            .rev()
-            .take(2)
-            .collect::<Vec<usize>>();
-        // The answer provided by AOC.
-        assert_eq!(top2_inspections[0] * top2_inspections[1], 11741456163);
+        let input_string =
+            io::read_string(Path::new("data/day11/data.txt")).expect("Unable to find input. ");
+        let troop = Troop::new(
+            monkeys_from_string(&input_string).unwrap(),
+            WorryPolicy::ModuloLcm,
+        )
+        .unwrap();
+        assert_eq!(
+            troop.fast_forward_monkey_business(10_000, 2).unwrap(),
+            11741456163
+        );
+        let mut inspections = troop.fast_forward_inspections(1_000_000_000_000).unwrap();
+        assert!(inspections.iter().all(|count| *count > 1_000_000_000));
+
+        // At this many rounds the monkey business no longer fits a u64.
+        inspections.sort_unstable_by(|a, b| b.cmp(a));
+        let business = troop
+            .fast_forward_monkey_business(1_000_000_000_000, 2)
+            .unwrap();
+        assert_eq!(business, inspections[0] as u128 * inspections[1] as u128);
+        assert!(business > u64::MAX as u128);
+        assert!(matches!(
+            troop.fast_forward_monkey_business(1_000_000_000_000, 8),
+            Err(Error::Overflow(_))
+        ));
+
+        // Dividing worry by 3 need not ever revisit a state, so there is no cycle to find.
+        let divided = Troop::new(
+            monkeys_from_string(&input_string).unwrap(),
+            WorryPolicy::DivideBy(3),
+        )
+        .unwrap();
+        assert!(matches!(
+            divided.fast_forward_inspections(20),
+            Err(Error::InvalidInput(_))
+        ));
     }
 }
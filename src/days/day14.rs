@@ -1,8 +1,9 @@
 use itertools::Itertools;
+use property::Property;
 use std::cmp::{max, min};
+use std::io::Read;
 
-use crate::io::read_string_col;
-use std::path::Path;
+use crate::err::Error;
 const CAVE_DIMS: (usize, usize) = (1000, 1000);
 
 #[derive(Debug, PartialEq, Eq)]
@@ -12,88 +13,244 @@ pub enum CavePoint {
     Sand,
 }
 
-pub fn parse_coords_set(instr: &String) -> Vec<(usize, usize)> {
-    instr
-        .split("->")
-        .into_iter()
-        .map(|segment_str| {
-            let segs: Vec<&str> = segment_str.split(",").collect();
-            let x = segs[0]
-                .trim()
-                .parse::<usize>()
-                .expect("Unable to parse x-coordinate. ");
-            let y = segs[1]
-                .trim()
-                .parse::<usize>()
-                .expect("Unable to parse x-coordinate. ");
-            (y, x)
-        })
-        .collect()
+/// A straight run of rock between two `(x, y)` points, inclusive of both ends.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RockSegment {
+    pub start: (usize, usize),
+    pub end: (usize, usize),
 }
 
-pub fn make_cave(rocks_path: &Path) -> Vec<Vec<CavePoint>> {
-    let mut cave: Vec<Vec<CavePoint>> = (0..CAVE_DIMS.0)
-        .map(|i| (0..CAVE_DIMS.1).map(|j| CavePoint::Air).collect())
-        .collect();
+impl RockSegment {
+    /// Iterates over every `(x, y)` point covered by the segment.
+    pub fn points(&self) -> impl Iterator<Item = (usize, usize)> {
+        let (x0, x1) = (min(self.start.0, self.end.0), max(self.start.0, self.end.0));
+        let (y0, y1) = (min(self.start.1, self.end.1), max(self.start.1, self.end.1));
+        (y0..=y1).flat_map(move |y| (x0..=x1).map(move |x| (x, y)))
+    }
+}
 
-    for rock_str in read_string_col(rocks_path).expect("Unable to load rocks from file. ") {
-        for (prev, curr) in parse_coords_set(&rock_str).iter().tuple_windows() {
-            if prev.0 == curr.0 {
-                for j in min(prev.1, curr.1)..max(prev.1, curr.1) {
-                    cave[curr.0][j] = CavePoint::Rock;
-                }
-            } else {
-                for i in min(prev.0, curr.0)..max(prev.0, curr.0) {
-                    cave[i][curr.1] = CavePoint::Rock;
+/// The smallest box, in `(x, y)` coordinates, that contains every rock.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BoundingBox {
+    pub min: (usize, usize),
+    pub max: (usize, usize),
+}
+
+/// The parsed cave. The occupancy grid is indexed as `grid[y][x]`.
+#[derive(Property, Debug)]
+#[property(get(public), set(disable), mut(disable))]
+pub struct Cave {
+    segments: Vec<RockSegment>,
+    bounds: BoundingBox,
+    #[property(get(type = "copy"))]
+    floor_level: usize,
+    #[property(mut(public))]
+    grid: Vec<Vec<CavePoint>>,
+}
+
+impl Cave {
+    /// The most points [`Cave::parse`] will allocate for the grid, about 64 MB.
+    pub const MAX_GRID_POINTS: usize = 1 << 26;
+
+    /// Parses the rock paths, one per line, e.g. `498,4 -> 498,6 -> 496,6`. The grid covers
+    /// every point from the origin, so rocks far from it are an error if the grid would hold
+    /// more than [`Cave::MAX_GRID_POINTS`] points.
+    pub fn parse(input: &str) -> Result<Cave, Error> {
+        let mut segments = vec![];
+        for (line_no, line) in input.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            let points = parse_rock_path(line, line_no + 1)?;
+            if points.len() == 1 {
+                let (_, point) = points[0];
+                segments.push(RockSegment {
+                    start: point,
+                    end: point,
+                });
+            }
+            for ((_, start), (column, end)) in points.into_iter().tuple_windows() {
+                if start.0 != end.0 && start.1 != end.1 {
+                    return Err(Error::ParseError {
+                        line: line_no + 1,
+                        column,
+                        message: format!(
+                            "Diagonal rock segment from {},{} to {},{}. ",
+                            start.0, start.1, end.0, end.1
+                        ),
+                    });
                 }
+                segments.push(RockSegment { start, end });
             }
-            cave[prev.0][prev.1] = CavePoint::Rock;
-            cave[curr.0][curr.1] = CavePoint::Rock;
         }
+
+        let bounds = segments
+            .iter()
+            .flat_map(|seg| [seg.start, seg.end])
+            .fold(None, |acc: Option<BoundingBox>, (x, y)| {
+                Some(match acc {
+                    None => BoundingBox {
+                        min: (x, y),
+                        max: (x, y),
+                    },
+                    Some(bb) => BoundingBox {
+                        min: (min(bb.min.0, x), min(bb.min.1, y)),
+                        max: (max(bb.max.0, x), max(bb.max.1, y)),
+                    },
+                })
+            })
+            .ok_or(Error::ParseError {
+                line: 1,
+                column: 1,
+                message: String::from("No rock paths found in input. "),
+            })?;
+        let floor_level = bounds.max.1.saturating_add(2);
+
+        // Leave enough room for a full pile of sand to rest on the floor.
+        let height = max(CAVE_DIMS.0, floor_level.saturating_add(1));
+        let width = max(
+            CAVE_DIMS.1,
+            bounds.max.0.saturating_add(floor_level).saturating_add(2),
+        );
+        if height.saturating_mul(width) > Self::MAX_GRID_POINTS {
+            return Err(Error::InvalidInput(format!(
+                "A {} by {} grid is too large for rocks reaching {},{}. ",
+                width, height, bounds.max.0, bounds.max.1
+            )));
+        }
+        let mut grid: Vec<Vec<CavePoint>> = (0..height)
+            .map(|_| (0..width).map(|_| CavePoint::Air).collect())
+            .collect();
+        for (x, y) in segments.iter().flat_map(|seg| seg.points()) {
+            grid[y][x] = CavePoint::Rock;
+        }
+
+        Ok(Cave {
+            segments,
+            bounds,
+            floor_level,
+            grid,
+        })
+    }
+
+    /// Reads the whole of `reader` and parses it as a set of rock paths.
+    pub fn from_reader<R: Read>(mut reader: R) -> Result<Cave, Error> {
+        let mut input = String::new();
+        reader.read_to_string(&mut input)?;
+        Cave::parse(&input)
     }
 
-    cave
+    /// Fills the row at `floor_level` with rock, as required for part 2.
+    pub fn add_floor(&mut self) {
+        for point in self.grid[self.floor_level].iter_mut() {
+            *point = CavePoint::Rock;
+        }
+    }
 }
 
-pub fn add_cave_floor(rocks_path: &Path, cave: &mut Vec<Vec<CavePoint>>) {
-    let rock_str = read_string_col(rocks_path).expect("Unable to load rocks from file. ");
-    let floor_level = rock_str
-        .iter()
-        .map(|instr| {
-            parse_coords_set(instr)
-                .iter()
-                .map(|(i, _j)| i.clone())
-                .collect::<Vec<usize>>()
-        })
-        .flatten()
-        .max()
-        .unwrap()
-        + 2;
+/// An `(x, y)` point, paired with the column it was read from.
+type ParsedPoint = (usize, (usize, usize));
 
-    println!("Adding floor at level {}", floor_level);
+/// Parses a single line of `x,y` points, returning each with the column it started at.
+fn parse_rock_path(line: &str, line_no: usize) -> Result<Vec<ParsedPoint>, Error> {
+    let mut offset = 0;
+    let mut points = vec![];
+    for segment_str in line.split("->") {
+        let mut coord_offset = offset;
+        let mut coords = vec![];
+        for coord_str in segment_str.split(',') {
+            let column = coord_offset + (coord_str.len() - coord_str.trim_start().len()) + 1;
+            let value = coord_str
+                .trim()
+                .parse::<usize>()
+                .map_err(|_| Error::ParseError {
+                    line: line_no,
+                    column,
+                    message: format!("Unable to parse coordinate '{}'. ", coord_str.trim()),
+                })?;
+            coords.push((column, value));
+            coord_offset += coord_str.len() + 1;
+        }
 
-    for j in 0..CAVE_DIMS.1 {
-        cave[floor_level][j] = CavePoint::Rock;
+        match coords[..] {
+            [(column, x), (_, y)] => points.push((column, (x, y))),
+            _ => {
+                return Err(Error::ParseError {
+                    line: line_no,
+                    column: coords[0].0,
+                    message: format!("Expected an x,y pair, found '{}'. ", segment_str.trim()),
+                })
+            }
+        }
+        offset += segment_str.len() + 2;
     }
+    Ok(points)
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::days::day14::add_cave_floor;
+    use super::{BoundingBox, Cave, CavePoint, RockSegment};
+    use crate::err::Error;
+    use std::fs::File;
+
+    #[test]
+    fn day14_parse_example() {
+        let cave = Cave::from_reader(File::open("data/day14/example.txt").unwrap()).unwrap();
+        assert_eq!(cave.segments().len(), 5);
+        assert_eq!(
+            cave.segments()[0],
+            RockSegment {
+                start: (498, 4),
+                end: (498, 6)
+            }
+        );
+        assert_eq!(
+            *cave.bounds(),
+            BoundingBox {
+                min: (494, 4),
+                max: (503, 9)
+            }
+        );
+        assert_eq!(cave.floor_level(), 11);
+        assert_eq!(cave.grid()[6][497], CavePoint::Rock);
+        assert_eq!(cave.grid()[5][497], CavePoint::Air);
+    }
 
-    use super::{make_cave, CavePoint};
-    use std::path::Path;
+    #[test]
+    fn day14_parse_errors() {
+        match Cave::parse("498,4 -> 498,6\n503,4 -> 502,x4") {
+            Err(Error::ParseError { line, column, .. }) => assert_eq!((line, column), (2, 14)),
+            other => panic!("Expected a parse error, got {:?}", other),
+        }
+        match Cave::parse("498,4 -> 500,6") {
+            Err(Error::ParseError { line, column, .. }) => assert_eq!((line, column), (1, 10)),
+            other => panic!("Expected a parse error, got {:?}", other),
+        }
+        assert!(matches!(
+            Cave::parse("100000000,5 -> 100000000,6"),
+            Err(Error::InvalidInput(_))
+        ));
+        assert!(matches!(
+            Cave::parse("500,5 -> 500,100000000"),
+            Err(Error::InvalidInput(_))
+        ));
+        let far = Cave::parse("5000,5 -> 5000,6").unwrap();
+        assert_eq!(far.grid()[6][5000], CavePoint::Rock);
+    }
 
     #[test]
     fn day14_part1() {
-        let mut cave = make_cave(Path::new("data/day14/data.txt"));
+        let mut cave = Cave::from_reader(File::open("data/day14/data.txt").unwrap())
+            .expect("Unable to load rocks from file. ");
         let start_point = (0_usize, 500_usize);
         let abyss_level = 999_usize;
         let mut abyss_reached = false;
         let mut n_sand_rest = 0_usize;
+        let cave = cave.mut_grid();
 
         while !abyss_reached {
-            let mut sand_coord = start_point.clone();
+            let mut sand_coord = start_point;
 
             loop {
                 if sand_coord.0 >= abyss_level {
@@ -133,8 +290,10 @@ mod tests {
 
     #[test]
     fn day14_part2() {
-        let mut cave = make_cave(Path::new("data/day14/data.txt"));
-        add_cave_floor(Path::new("data/day14/data.txt"), &mut cave);
+        let mut cave = Cave::from_reader(File::open("data/day14/data.txt").unwrap())
+            .expect("Unable to load rocks from file. ");
+        cave.add_floor();
+        let cave = cave.mut_grid();
 
         let start_point = (0_usize, 500_usize);
         let end_coord = start_point;
@@ -142,7 +301,7 @@ mod tests {
         let mut n_sand_rest = 0_usize;
 
         while !start_reached {
-            let mut sand_coord = start_point.clone();
+            let mut sand_coord = start_point;
 
             loop {
                 let down = (sand_coord.0 + 1, sand_coord.1);
@@ -1,237 +1,1628 @@
-use std::{collections::VecDeque, vec};
+use serde_json as json;
+use std::cell::OnceCell;
+use std::collections::{HashMap, HashSet};
+use std::fmt::Display;
+
+use crate::err::Error;
 
 #[derive(Debug, Clone)]
 pub enum Token {
-    Command { cmd: String, args: Vec<String> },
-    DirectoryItem { name: String },
-    FileItem { name: String, size: usize },
-}
-
-#[derive(Debug)]
-pub enum TreeItem {
-    Directory {
+    Command {
+        cmd: String,
+        args: Vec<String>,
+    },
+    DirectoryItem {
         name: String,
-        children: Vec<TreeItem>,
     },
-    File {
+    FileItem {
         name: String,
         size: usize,
     },
+    /// A line printed by a command other than `ls`, or a blank line.
+    Output {
+        text: String,
+    },
 }
 
-impl TreeItem {
-    pub fn from_tokens(name: String, tokens: &mut VecDeque<Token>) -> TreeItem {
-        let mut current_item = TreeItem::Directory {
-            name,
-            children: vec![],
-        };
-        current_item.process_tokens(tokens);
-        current_item
-    }
-
-    pub fn size(&self) -> usize {
-        match self {
-            Self::Directory {
-                name: _,
-                ref children,
-            } => children.iter().map(Self::size).sum(),
-            Self::File { name: _, ref size } => size.clone(),
-        }
-    }
-
-    pub fn process_tokens(&mut self, tokens: &mut VecDeque<Token>) {
-        while let Some(tok) = tokens.pop_front() {
-            match tok {
-                Token::FileItem { ref name, ref size } => match self {
-                    TreeItem::Directory { name: _, children } => children.push(Self::File {
-                        name: name.clone(),
-                        size: size.clone(),
-                    }),
-                    _ => panic!(),
-                },
-                Token::DirectoryItem { ref name } => match self {
-                    TreeItem::Directory { name: _, children } => children.push(Self::Directory {
-                        name: name.clone(),
-                        children: vec![],
-                    }),
-                    _ => panic!(),
-                },
-                Token::Command { ref cmd, ref args } => match cmd.as_str() {
-                    "cd" => {
-                        let target_dir = args.first().expect("expect arg after cd. ");
-                        match target_dir.as_str() {
-                            ".." => break,
-                            _ => match self.find_item(target_dir) {
-                                Some(dir) => dir.process_tokens(tokens),
-                                None => panic!("{} not found in directory", target_dir),
-                            },
-                        }
+/// The index of a [`Node`] within a [`FileSystem`].
+pub type NodeId = usize;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NodeKind {
+    Directory { children: Vec<NodeId> },
+    File { size: usize },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Node {
+    pub name: String,
+    /// The directory holding this node, which is `None` only for the root.
+    pub parent: Option<NodeId>,
+    pub kind: NodeKind,
+}
+
+impl Node {
+    pub fn is_dir(&self) -> bool {
+        matches!(self.kind, NodeKind::Directory { .. })
+    }
+}
+
+/// A filesystem rebuilt from a terminal session. Nodes live in a single arena and refer to
+/// each other by index, with the root directory at index 0.
+#[derive(Debug, Clone)]
+pub struct FileSystem {
+    nodes: Vec<Node>,
+    cwd: NodeId,
+    /// The total size of every node, computed on first use and cleared by any change.
+    sizes: OnceCell<Vec<usize>>,
+}
+
+impl Default for FileSystem {
+    fn default() -> Self {
+        FileSystem::new()
+    }
+}
+
+impl FileSystem {
+    /// Creates a filesystem holding only an empty root directory.
+    pub fn new() -> FileSystem {
+        FileSystem {
+            nodes: vec![Node {
+                name: String::from("/"),
+                parent: None,
+                kind: NodeKind::Directory { children: vec![] },
+            }],
+            cwd: 0,
+            sizes: OnceCell::new(),
+        }
+    }
+
+    pub fn root(&self) -> NodeId {
+        0
+    }
+
+    /// The current working directory.
+    pub fn cwd(&self) -> NodeId {
+        self.cwd
+    }
+
+    pub fn node(&self, id: NodeId) -> &Node {
+        &self.nodes[id]
+    }
+
+    pub fn nodes(&self) -> &[Node] {
+        &self.nodes
+    }
+
+    /// The children of `id`, which is empty for a file.
+    pub fn children(&self, id: NodeId) -> &[NodeId] {
+        match &self.nodes[id].kind {
+            NodeKind::Directory { children } => children,
+            NodeKind::File { .. } => &[],
+        }
+    }
+
+    /// The child of directory `dir` called `name`, if there is one.
+    pub fn child(&self, dir: NodeId, name: &str) -> Option<NodeId> {
+        self.children(dir)
+            .iter()
+            .copied()
+            .find(|id| self.nodes[*id].name == name)
+    }
+
+    /// The absolute path of `id`, such as `/a/e/i`.
+    pub fn path(&self, id: NodeId) -> String {
+        let mut names = vec![];
+        let mut current = id;
+        while let Some(parent) = self.nodes[current].parent {
+            names.push(self.nodes[current].name.as_str());
+            current = parent;
+        }
+        names.reverse();
+        format!("/{}", names.join("/"))
+    }
+
+    /// Follows `path` from the current directory, or from the root if it starts with `/`.
+    /// `.` and `..` are understood, and `..` at the root stays at the root.
+    pub fn resolve(&self, path: &str) -> Result<NodeId, Error> {
+        let mut current = if path.starts_with('/') {
+            self.root()
+        } else {
+            self.cwd
+        };
+        for segment in path.split('/') {
+            current = match segment {
+                "" | "." => current,
+                ".." => self.nodes[current].parent.unwrap_or(current),
+                name => {
+                    if !self.nodes[current].is_dir() {
+                        return Err(self.path_error(path, "Not a directory. "));
                     }
-                    _ => {}
-                },
+                    self.child(current, name)
+                        .ok_or_else(|| self.path_error(path, "No such file or directory. "))?
+                }
+            };
+        }
+        Ok(current)
+    }
+
+    /// Finds a node by its absolute path, such as `/a/e/i`.
+    pub fn lookup(&self, path: &str) -> Option<NodeId> {
+        if !path.starts_with('/') {
+            return None;
+        }
+        self.resolve(path).ok()
+    }
+
+    /// Changes the current directory to `path`.
+    pub fn cd(&mut self, path: &str) -> Result<NodeId, Error> {
+        let target = self.resolve(path)?;
+        if !self.nodes[target].is_dir() {
+            return Err(self.path_error(path, "Not a directory. "));
+        }
+        self.cwd = target;
+        Ok(target)
+    }
+
+    /// Adds a directory called `name` to `parent`, returning the existing one if it has
+    /// already been listed.
+    pub fn add_dir(&mut self, parent: NodeId, name: &str) -> Result<NodeId, Error> {
+        match self.child(parent, name) {
+            Some(id) if self.nodes[id].is_dir() => Ok(id),
+            Some(id) => Err(self.path_error(&self.path(id), "A file already has this name. ")),
+            None => self.insert(parent, name, NodeKind::Directory { children: vec![] }),
+        }
+    }
+
+    /// Adds a file called `name` to `parent`. A file that has already been listed takes on
+    /// the new size.
+    pub fn add_file(&mut self, parent: NodeId, name: &str, size: usize) -> Result<NodeId, Error> {
+        match self.child(parent, name) {
+            Some(id) if self.nodes[id].is_dir() => {
+                Err(self.path_error(&self.path(id), "A directory already has this name. "))
+            }
+            Some(id) => {
+                self.nodes[id].kind = NodeKind::File { size };
+                self.sizes.take();
+                Ok(id)
+            }
+            None => self.insert(parent, name, NodeKind::File { size }),
+        }
+    }
+
+    /// The total size of `id`, summed over everything beneath it for a directory.
+    pub fn size(&self, id: NodeId) -> usize {
+        self.sizes()[id]
+    }
+
+    /// The total size of every node, indexed by id.
+    pub fn sizes(&self) -> &[usize] {
+        self.sizes.get_or_init(|| {
+            let mut sizes = vec![0; self.nodes.len()];
+            // Children follow their parents in a pre-order walk, so walking it backwards
+            // finishes every child before its parent.
+            for id in self.descendants(self.root()).into_iter().rev() {
+                sizes[id] = match &self.nodes[id].kind {
+                    NodeKind::File { size } => *size,
+                    NodeKind::Directory { children } => children.iter().map(|c| sizes[*c]).sum(),
+                };
+            }
+            sizes
+        })
+    }
+
+    /// `id` and everything beneath it, in pre-order with children in listing order.
+    pub fn descendants(&self, id: NodeId) -> Vec<NodeId> {
+        let mut order = vec![];
+        let mut stack = vec![id];
+        while let Some(current) = stack.pop() {
+            order.push(current);
+            stack.extend(self.children(current).iter().rev());
+        }
+        order
+    }
+
+    /// Every directory, including the root, in pre-order.
+    pub fn dirs(&self) -> impl Iterator<Item = NodeId> + '_ {
+        self.descendants(self.root())
+            .into_iter()
+            .filter(|id| self.nodes[*id].is_dir())
+    }
+
+    /// The path and total size of every directory, in pre-order.
+    pub fn dir_sizes(&self) -> impl Iterator<Item = (String, usize)> + '_ {
+        self.dirs().map(|id| (self.path(id), self.size(id)))
+    }
+
+    /// The path and total size of every directory matching `predicate`.
+    pub fn dirs_where<'a, P>(
+        &'a self,
+        mut predicate: P,
+    ) -> impl Iterator<Item = (String, usize)> + 'a
+    where
+        P: FnMut(&str, usize) -> bool + 'a,
+    {
+        self.dir_sizes()
+            .filter(move |(path, size)| predicate(path, *size))
+    }
+
+    /// The total size of everything in the filesystem.
+    pub fn used_space(&self) -> usize {
+        self.size(self.root())
+    }
+
+    /// Whether `ancestor` is `id` or one of the directories above it.
+    pub fn is_within(&self, id: NodeId, ancestor: NodeId) -> bool {
+        let mut current = Some(id);
+        while let Some(node) = current {
+            if node == ancestor {
+                return true;
+            }
+            current = self.nodes[node].parent;
+        }
+        false
+    }
+
+    /// Whether `id` can still be reached from the root.
+    pub fn is_attached(&self, id: NodeId) -> bool {
+        let mut current = id;
+        while let Some(parent) = self.nodes[current].parent {
+            if !self.children(parent).contains(&current) {
+                return false;
+            }
+            current = parent;
+        }
+        current == self.root()
+    }
+
+    /// Detaches `id`, and everything beneath it, from its directory. Removed nodes stay in
+    /// the arena but can no longer be reached from the root.
+    pub fn remove(&mut self, id: NodeId) -> Result<(), Error> {
+        let parent = match self.nodes[id].parent {
+            Some(parent) => parent,
+            None => return Err(self.path_error("/", "Cannot remove the root. ")),
+        };
+        if let NodeKind::Directory { children } = &mut self.nodes[parent].kind {
+            children.retain(|child| *child != id);
+        }
+        self.sizes.take();
+        Ok(())
+    }
+
+    /// Moves `id` into the directory `parent`, under the name `name`.
+    pub fn move_node(&mut self, id: NodeId, parent: NodeId, name: &str) -> Result<(), Error> {
+        if self.nodes[id].parent.is_none() {
+            return Err(self.path_error("/", "Cannot move the root. "));
+        }
+        if self.is_within(parent, id) {
+            return Err(self.path_error(&self.path(id), "Cannot move a directory inside itself. "));
+        }
+        if !self.nodes[parent].is_dir() {
+            return Err(self.path_error(&self.path(parent), "Not a directory. "));
+        }
+        match self.child(parent, name) {
+            Some(existing) if existing == id => return Ok(()),
+            Some(existing) => {
+                return Err(self.path_error(&self.path(existing), "Already exists. "));
+            }
+            None => {}
+        }
+        if name.is_empty() || name.contains('/') || name == "." || name == ".." {
+            return Err(self.path_error(name, "Invalid name. "));
+        }
+
+        self.remove(id)?;
+        if let NodeKind::Directory { children } = &mut self.nodes[parent].kind {
+            children.push(id);
+        }
+        self.nodes[id].parent = Some(parent);
+        self.nodes[id].name = name.to_string();
+        Ok(())
+    }
+
+    fn insert(&mut self, parent: NodeId, name: &str, kind: NodeKind) -> Result<NodeId, Error> {
+        if name.is_empty() || name.contains('/') || name == "." || name == ".." {
+            return Err(self.path_error(name, "Invalid name. "));
+        }
+        let id = self.nodes.len();
+        match &mut self.nodes[parent].kind {
+            NodeKind::Directory { children } => children.push(id),
+            NodeKind::File { .. } => {
+                return Err(self.path_error(&self.path(parent), "Not a directory. "))
             }
         }
+        self.nodes.push(Node {
+            name: name.to_string(),
+            parent: Some(parent),
+            kind,
+        });
+        self.sizes.take();
+        Ok(id)
+    }
+
+    fn path_error(&self, path: &str, message: &str) -> Error {
+        Error::PathError {
+            path: path.to_string(),
+            message: message.to_string(),
+        }
+    }
+}
+
+impl FileSystem {
+    /// Draws the filesystem in the style of `tree`, with the total size of every node and
+    /// each directory's entries sorted by name.
+    pub fn tree(&self) -> String {
+        let mut out = format!("/ ({})\n", self.used_space());
+        self.tree_lines(self.root(), "", &mut out);
+        out
+    }
+
+    fn tree_lines(&self, dir: NodeId, prefix: &str, out: &mut String) {
+        let children = self.sorted_children(dir);
+        for (i, child) in children.iter().enumerate() {
+            let last = i + 1 == children.len();
+            let node = &self.nodes[*child];
+            let slash = if node.is_dir() { "/" } else { "" };
+            out.push_str(&format!(
+                "{}{}{}{} ({})\n",
+                prefix,
+                if last { "└── " } else { "├── " },
+                node.name,
+                slash,
+                self.size(*child)
+            ));
+            let prefix = format!("{}{}", prefix, if last { "    " } else { "│   " });
+            self.tree_lines(*child, &prefix, out);
+        }
+    }
+
+    /// Summarises every directory in the style of `du -h`, largest first.
+    pub fn du(&self) -> String {
+        let mut dirs: Vec<(String, usize)> = self.dir_sizes().collect();
+        dirs.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        dirs.iter()
+            .map(|(path, size)| format!("{}\t{}\n", human_size(*size), path))
+            .collect()
     }
 
-    pub fn find_item(&mut self, search_name: &String) -> Option<&mut TreeItem> {
-        match self {
-            Self::File { name: _, size: _ } => None,
-            Self::Directory { name: _, children } => children.iter_mut().find(|item| match *item {
-                Self::File { ref name, size: _ } => name == search_name,
-                Self::Directory {
-                    ref name,
-                    children: _,
-                } => name == search_name,
+    /// The hierarchy below the root as JSON, with directory entries sorted by name.
+    pub fn to_json(&self) -> json::Value {
+        self.node_json(self.root())
+    }
+
+    fn node_json(&self, id: NodeId) -> json::Value {
+        let node = &self.nodes[id];
+        match node.kind {
+            NodeKind::File { size } => json::json!({
+                "name": node.name,
+                "type": "file",
+                "size": size,
+            }),
+            NodeKind::Directory { .. } => json::json!({
+                "name": node.name,
+                "type": "directory",
+                "size": self.size(id),
+                "children": self
+                    .sorted_children(id)
+                    .into_iter()
+                    .map(|child| self.node_json(child))
+                    .collect::<Vec<json::Value>>(),
             }),
         }
     }
 
-    pub fn find_dirs_smaller_than(&self, target_size: usize) -> Vec<&TreeItem> {
-        match self {
-            Self::Directory {
-                name: _,
-                ref children,
-            } => {
-                let this_dir = if self.size() <= target_size {
-                    vec![self]
-                } else {
-                    vec![]
-                };
-                [
-                    this_dir,
-                    children
-                        .iter()
-                        .map(|item| item.find_dirs_smaller_than(target_size))
-                        .flatten()
-                        .collect::<Vec<&TreeItem>>(),
-                ]
-                .concat()
-            }
-            Self::File { name: _, size: _ } => vec![],
-        }
-    }
-
-    pub fn find_dirs_larger_than(&self, target_size: usize) -> Vec<&TreeItem> {
-        match self {
-            Self::Directory {
-                name: _,
-                ref children,
-            } => {
-                let this_dir = if self.size() >= target_size {
-                    vec![self]
-                } else {
-                    vec![]
+    fn sorted_children(&self, dir: NodeId) -> Vec<NodeId> {
+        let mut children = self.children(dir).to_vec();
+        children.sort_by(|a, b| self.nodes[*a].name.cmp(&self.nodes[*b].name));
+        children
+    }
+}
+
+/// Formats a byte count as `du -h` does, rounding up to one decimal place below ten and to a
+/// whole number above, in powers of 1024.
+pub fn human_size(bytes: usize) -> String {
+    const UNITS: [&str; 5] = ["", "K", "M", "G", "T"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit + 1 < UNITS.len() {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        bytes.to_string()
+    } else if value < 10.0 && (value * 10.0).ceil() < 100.0 {
+        format!("{:.1}{}", (value * 10.0).ceil() / 10.0, UNITS[unit])
+    } else {
+        format!("{}{}", value.ceil(), UNITS[unit])
+    }
+}
+
+/// What a cleanup plan should keep as small as possible.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CleanupGoal {
+    /// Delete as few things as possible, and then as few bytes as possible.
+    FewestDeletions,
+    /// Delete as few bytes as possible.
+    FewestBytes,
+}
+
+/// Which kinds of node a cleanup plan may delete.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Deletable {
+    Directories,
+    Files,
+    Both,
+}
+
+/// A request to free space on a disk. The default matches the puzzle: a 70,000,000 byte disk
+/// that needs 30,000,000 bytes free, cleaned up by deleting directories.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cleanup {
+    pub capacity: usize,
+    pub target_free: usize,
+    pub goal: CleanupGoal,
+    pub deletable: Deletable,
+    /// The most `(deletions, bytes)` states [`Cleanup::plan`] may track. Each costs about
+    /// four bytes, so the default of 2^25 is enough for the puzzle in about 150 MB.
+    pub max_states: usize,
+}
+
+impl Default for Cleanup {
+    fn default() -> Self {
+        Cleanup {
+            capacity: 70_000_000,
+            target_free: 30_000_000,
+            goal: CleanupGoal::FewestBytes,
+            deletable: Deletable::Directories,
+            max_states: 1 << 25,
+        }
+    }
+}
+
+/// The nodes to delete, as `(path, size)` sorted by path. None of them lies inside another.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CleanupPlan {
+    pub deletions: Vec<(String, usize)>,
+    pub freed: usize,
+}
+
+impl Cleanup {
+    /// How many more bytes must be freed to reach the target.
+    pub fn space_needed(&self, fs: &FileSystem) -> usize {
+        (fs.used_space() + self.target_free).saturating_sub(self.capacity)
+    }
+
+    /// Finds an optimal set of nodes to delete, or `None` if even deleting everything
+    /// allowed would not free enough. The root itself is never deleted.
+    ///
+    /// This is a subset sum over sets of nodes where none contains another, so it takes
+    /// time proportional to the number of nodes times the bytes needed, and memory
+    /// proportional to the bytes needed. For [`CleanupGoal::FewestDeletions`] both are also
+    /// multiplied by the number of deletions. Returns an error, rather than running out of
+    /// memory, if this would track more than `max_states` states.
+    pub fn plan(&self, fs: &FileSystem) -> Result<Option<CleanupPlan>, Error> {
+        let needed = self.space_needed(fs);
+        if needed == 0 {
+            return Ok(Some(CleanupPlan {
+                deletions: vec![],
+                freed: 0,
+            }));
+        }
+        if needed > fs.used_space() {
+            return Ok(None);
+        }
+
+        // Children come before their parents, and each subtree is a contiguous block that
+        // ends with its own root, starting at `first`.
+        let sizes = fs.sizes();
+        let order: Vec<NodeId> = fs
+            .descendants(fs.root())
+            .into_iter()
+            .skip(1)
+            .rev()
+            .collect();
+        let mut count = vec![1; fs.nodes().len()];
+        let mut first = vec![0; order.len()];
+        for (i, &id) in order.iter().enumerate() {
+            count[id] += fs.children(id).iter().map(|c| count[*c]).sum::<usize>();
+            first[i] = i + 1 - count[id];
+        }
+        let takeable = |id: NodeId| match self.deletable {
+            Deletable::Directories => fs.node(id).is_dir(),
+            Deletable::Files => !fs.node(id).is_dir(),
+            Deletable::Both => true,
+        };
+
+        // With one layer every deletion stays in it; otherwise layer k holds sums made of
+        // exactly k deletions.
+        let (n_layers, step) = match self.goal {
+            CleanupGoal::FewestBytes => (1, 0),
+            CleanupGoal::FewestDeletions => match min_deletions(fs, &order, &takeable, needed) {
+                Some(k) => (k + 1, 1),
+                None => return Ok(None),
+            },
+        };
+        if n_layers.saturating_mul(needed) > self.max_states {
+            return Err(Error::InvalidInput(format!(
+                "Freeing {} bytes in {} layers needs more than {} states. ",
+                needed, n_layers, self.max_states
+            )));
+        }
+
+        let words = needed.div_ceil(64);
+        // The sets of sums below `needed` that can be reached, one bitset per layer, and the
+        // highest word of each that may hold a set bit.
+        let mut reached = vec![vec![0_u64; words]; n_layers];
+        let mut top = vec![0; n_layers];
+        reached[0][0] = 1;
+        // For each sum, one more than the index of the deletion that first reached it, so
+        // that a plan can be traced back. The empty plan is marked with 0.
+        let mut reached_at = vec![vec![u32::MAX; needed]; n_layers];
+        reached_at[0][0] = 0;
+        // The smallest sum of at least `needed` in each layer, as (total, index, source).
+        let mut complete: Vec<Option<(usize, usize, usize)>> = vec![None; n_layers];
+
+        // Deleting a node excludes everything beneath it, so it adds to the sums that were
+        // reachable before its block began.
+        let mut open = HashMap::new();
+        for (i, &id) in order.iter().enumerate() {
+            if takeable(id) && first[i] < i {
+                *open.entry(first[i]).or_insert(0) += 1;
+            }
+        }
+        let mut snapshots: HashMap<usize, (Vec<Vec<u64>>, Vec<usize>)> = HashMap::new();
+
+        for (i, &id) in order.iter().enumerate() {
+            if open.contains_key(&i) {
+                snapshots.insert(i, (reached.clone(), top.clone()));
+            }
+            if !takeable(id) {
+                continue;
+            }
+
+            // A leaf adds to the sums reached so far, in place. Layers are updated from the
+            // top down, and words from the highest down, so that each is read before this
+            // deletion is added to it.
+            let size = sizes[id];
+            let (offset, rem) = (size / 64, size % 64);
+            let snapshot = (first[i] < i).then(|| &snapshots[&first[i]]);
+            for layer in (0..n_layers - step).rev() {
+                let dest = layer + step;
+                let source_top = snapshot.map_or(top[layer], |(_, tops)| tops[layer]);
+                let read = |reached: &[Vec<u64>], w: usize| match snapshot {
+                    Some((bits, _)) => bits[layer][w],
+                    None => reached[layer][w],
                 };
-                [
-                    this_dir,
-                    children
-                        .iter()
-                        .map(|item| item.find_dirs_larger_than(target_size))
-                        .flatten()
-                        .collect::<Vec<&TreeItem>>(),
-                ]
-                .concat()
+
+                let lo = needed.saturating_sub(size);
+                let smallest = (lo / 64..=source_top).find_map(|w| {
+                    let mut word = read(&reached, w);
+                    if w == lo / 64 {
+                        word &= u64::MAX << (lo % 64);
+                    }
+                    (word != 0).then(|| w * 64 + word.trailing_zeros() as usize)
+                });
+                if let Some(s) = smallest {
+                    let improves = match complete[dest] {
+                        Some((total, _, _)) => s + size < total,
+                        None => true,
+                    };
+                    if improves {
+                        complete[dest] = Some((s + size, i, s));
+                    }
+                }
+
+                if offset >= words {
+                    continue;
+                }
+                for w in (offset..=(source_top + offset + 1).min(words - 1)).rev() {
+                    let mut bits = read(&reached, w - offset) << rem;
+                    if rem > 0 && w > offset {
+                        bits |= read(&reached, w - offset - 1) >> (64 - rem);
+                    }
+                    if w == words - 1 {
+                        // Only sums below `needed` are kept.
+                        bits &= u64::MAX >> (words * 64 - needed);
+                    }
+                    let mut new = bits & !reached[dest][w];
+                    if new != 0 {
+                        top[dest] = top[dest].max(w);
+                    }
+                    reached[dest][w] |= new;
+                    while new != 0 {
+                        reached_at[dest][w * 64 + new.trailing_zeros() as usize] = i as u32 + 1;
+                        new &= new - 1;
+                    }
+                }
+            }
+            if first[i] < i {
+                let refs = open.get_mut(&first[i]).unwrap();
+                *refs -= 1;
+                if *refs == 0 {
+                    snapshots.remove(&first[i]);
+                }
             }
-            Self::File { name: _, size: _ } => vec![],
         }
+
+        let layer = n_layers - 1;
+        let (freed, last, mut s) = match complete[layer] {
+            Some(found) => found,
+            None => return Ok(None),
+        };
+        let mut chosen = vec![order[last]];
+        let mut layer = layer - step;
+        while reached_at[layer][s] != 0 {
+            let id = order[reached_at[layer][s] as usize - 1];
+            chosen.push(id);
+            s -= sizes[id];
+            layer -= step;
+        }
+
+        let mut deletions: Vec<(String, usize)> =
+            chosen.iter().map(|id| (fs.path(*id), sizes[*id])).collect();
+        deletions.sort();
+        Ok(Some(CleanupPlan { deletions, freed }))
     }
 }
 
-pub fn tokenise_vec(str_vec: &Vec<String>) -> Vec<Token> {
-    str_vec
+/// The fewest deletions that free at least `needed` bytes, found from the most bytes each
+/// subtree can free with a given number of deletions.
+fn min_deletions<F: Fn(NodeId) -> bool>(
+    fs: &FileSystem,
+    order: &[NodeId],
+    takeable: &F,
+    needed: usize,
+) -> Option<usize> {
+    let max_plus = |a: &[usize], b: &[usize]| {
+        let mut out = vec![0; a.len() + b.len() - 1];
+        for (i, x) in a.iter().enumerate() {
+            for (j, y) in b.iter().enumerate() {
+                out[i + j] = out[i + j].max(x + y);
+            }
+        }
+        out
+    };
+
+    // For each node, the most bytes that can be freed beneath it with at most k deletions.
+    let mut most: Vec<Vec<usize>> = vec![vec![]; fs.nodes().len()];
+    let freeable = |id: NodeId, most: &mut Vec<Vec<usize>>| {
+        let mut acc = vec![0];
+        for child in fs.children(id) {
+            acc = max_plus(&acc, &std::mem::take(&mut most[*child]));
+        }
+        acc
+    };
+    for &id in order {
+        let mut acc = freeable(id, &mut most);
+        if takeable(id) {
+            if acc.len() < 2 {
+                acc.push(0);
+            }
+            acc[1] = acc[1].max(fs.size(id));
+            for k in 2..acc.len() {
+                acc[k] = acc[k].max(acc[k - 1]);
+            }
+        }
+        most[id] = acc;
+    }
+    freeable(fs.root(), &mut most)
         .iter()
-        .map(|line| {
-            let segments: Vec<&str> = line.split(" ").collect();
-
-            match *segments.first().expect("Empty line. ") {
-                "$" => Token::Command {
-                    cmd: segments[1].to_string(),
-                    args: segments
-                        .into_iter()
-                        .skip(2)
-                        .map(str::to_string)
-                        .collect::<Vec<String>>(),
-                },
-                "dir" => Token::DirectoryItem {
-                    name: segments[1].to_string(),
-                },
-                num if num.trim().parse::<usize>().is_ok() => Token::FileItem {
-                    name: segments[1].to_string(),
-                    size: num
-                        .trim()
-                        .parse::<usize>()
-                        .expect("Number is not a number. "),
-                },
-                _ => panic!("Unexpected state reached when parsing tokens. "),
+        .position(|bytes| *bytes >= needed)
+}
+
+/// Splits a transcript into tokens, one per line. Lines following `$ ls` are read as a
+/// listing, and lines following any other command as that command's output.
+pub fn tokenise_vec(str_vec: &[String]) -> Result<Vec<Token>, Error> {
+    let mut in_listing = false;
+    let mut tokens = Vec::with_capacity(str_vec.len());
+    for (line_no, line) in str_vec.iter().enumerate() {
+        let parse_error = |column: usize, message: String| Error::ParseError {
+            line: line_no + 1,
+            column,
+            message,
+        };
+
+        let token = if let Some(command) = line.strip_prefix("$ ") {
+            let mut words = split_words(command).map_err(|message| parse_error(3, message))?;
+            if words.is_empty() {
+                return Err(parse_error(3, String::from("Missing command. ")));
             }
-        })
-        .collect()
+            let cmd = words.remove(0);
+            in_listing = cmd == "ls";
+            Token::Command { cmd, args: words }
+        } else if !in_listing || line.trim().is_empty() {
+            Token::Output {
+                text: line.to_string(),
+            }
+        } else if let Some(name) = line.strip_prefix("dir ") {
+            Token::DirectoryItem {
+                name: name.to_string(),
+            }
+        } else {
+            let (size, name) = line.split_once(' ').ok_or_else(|| {
+                parse_error(
+                    1,
+                    format!("Expected a directory or file, found '{}'. ", line),
+                )
+            })?;
+            let size = size
+                .parse::<usize>()
+                .map_err(|_| parse_error(1, format!("Unable to parse file size '{}'. ", size)))?;
+            Token::FileItem {
+                name: name.to_string(),
+                size,
+            }
+        };
+        tokens.push(token);
+    }
+    Ok(tokens)
+}
+
+/// Splits a command line into words as a shell would, honouring single and double quotes
+/// and backslash escapes, so that paths may contain spaces.
+fn split_words(line: &str) -> Result<Vec<String>, String> {
+    let mut words = vec![];
+    let mut word: Option<String> = None;
+    let mut quote: Option<char> = None;
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some('"'), '\\') | (None, '\\') => match chars.next() {
+                Some(escaped) => word.get_or_insert_with(String::new).push(escaped),
+                None => return Err(String::from("Trailing backslash. ")),
+            },
+            (Some(_), c) => word.get_or_insert_with(String::new).push(c),
+            (None, '\'') | (None, '"') => {
+                quote = Some(c);
+                word.get_or_insert_with(String::new);
+            }
+            (None, c) if c.is_whitespace() => words.extend(word.take()),
+            (None, c) => word.get_or_insert_with(String::new).push(c),
+        }
+    }
+    if quote.is_some() {
+        return Err(String::from("Unterminated quote. "));
+    }
+    words.extend(word);
+    Ok(words)
+}
+
+/// Something in a transcript that does not agree with the filesystem built so far.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Warning {
+    pub line: usize,
+    pub message: String,
+}
+
+impl Display for Warning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+/// The `ls` whose output is being read, and the entries it has shown so far.
+#[derive(Debug)]
+struct Listing {
+    dir: Option<NodeId>,
+    seen: HashSet<NodeId>,
+}
+
+/// Replays a terminal transcript against a [`FileSystem`]. Besides `cd` and `ls`, it
+/// understands `mkdir [-p]`, `rm [-rf]`, `touch [size] <path>`, `mv` and `pwd`, applying each
+/// as a change to the filesystem. Anything that contradicts what is already known, such as
+/// an `ls` that leaves out an entry, becomes a [`Warning`], and the transcript wins.
+#[derive(Debug, Default)]
+pub struct Shell {
+    fs: FileSystem,
+    warnings: Vec<Warning>,
+    line: usize,
+    listing: Option<Listing>,
+    expect_pwd: bool,
 }
 
-pub fn parse_tree_from_tokens(tokens: &Vec<Token>) -> TreeItem {
-    let mut tok_vec = VecDeque::from(tokens.clone());
+impl Shell {
+    pub fn new() -> Shell {
+        Shell::default()
+    }
+
+    pub fn fs(&self) -> &FileSystem {
+        &self.fs
+    }
+
+    pub fn into_fs(self) -> FileSystem {
+        self.fs
+    }
+
+    pub fn warnings(&self) -> &[Warning] {
+        &self.warnings
+    }
+
+    /// Applies each token in turn, counting one line per token across calls. Only a `cd`
+    /// with more than one argument is an error; a `cd` to a directory that was never listed
+    /// creates it, with a warning.
+    pub fn run(&mut self, tokens: &[Token]) -> Result<(), Error> {
+        for tok in tokens {
+            self.line += 1;
+            self.apply(tok)?;
+        }
+        self.finish_listing();
+        Ok(())
+    }
+
+    fn apply(&mut self, tok: &Token) -> Result<(), Error> {
+        match tok {
+            Token::Command { cmd, args } => {
+                self.finish_listing();
+                self.expect_pwd = false;
+                self.command(cmd, args)?;
+            }
+            Token::DirectoryItem { name } => self.list_entry(name, None),
+            Token::FileItem { name, size } => self.list_entry(name, Some(*size)),
+            Token::Output { text } => {
+                if self.expect_pwd && !text.trim().is_empty() {
+                    self.expect_pwd = false;
+                    let cwd = self.fs.path(self.fs.cwd());
+                    if text.trim() != cwd {
+                        self.warn(format!(
+                            "pwd printed '{}', expected '{}'. ",
+                            text.trim(),
+                            cwd
+                        ));
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn command(&mut self, cmd: &str, args: &[String]) -> Result<(), Error> {
+        let (flags, paths): (Vec<&String>, Vec<&String>) = args
+            .iter()
+            .partition(|arg| arg.starts_with('-') && arg.len() > 1);
+        let has_flag = |c: char| flags.iter().any(|flag| flag.contains(c));
+
+        match cmd {
+            "cd" => {
+                if paths.len() > 1 {
+                    return Err(self.line_error("cd: too many arguments. "));
+                }
+                let target = paths.first().map_or("/", |path| path.as_str());
+                if self.fs.resolve(target).is_err() {
+                    self.warn(format!("cd: {} was never listed, creating it. ", target));
+                    self.mkdir(target, true);
+                }
+                if let Err(Error::PathError { path, message }) = self.fs.cd(target) {
+                    self.warn(format!("cd: {}: {}", path, message));
+                }
+            }
+            "ls" => {
+                let dir = match paths[..] {
+                    [] => Some(self.fs.cwd()),
+                    [path] => match self.fs.resolve(path) {
+                        Ok(id) if self.fs.node(id).is_dir() => Some(id),
+                        Ok(_) => None,
+                        Err(err) => {
+                            self.warn_err(err);
+                            None
+                        }
+                    },
+                    _ => {
+                        self.warn(String::from("ls of several paths is not supported. "));
+                        None
+                    }
+                };
+                self.listing = Some(Listing {
+                    dir,
+                    seen: HashSet::new(),
+                });
+            }
+            "mkdir" => {
+                for path in paths {
+                    self.mkdir(path, has_flag('p'));
+                }
+            }
+            "rm" => {
+                for path in paths {
+                    self.rm(path, has_flag('r') || has_flag('R'), has_flag('f'));
+                }
+            }
+            "touch" => {
+                let size = match paths[..] {
+                    [first, _, ..] => first.parse::<usize>().ok(),
+                    _ => None,
+                };
+                for path in paths.iter().skip(size.is_some() as usize) {
+                    self.touch(path, size);
+                }
+            }
+            "mv" => match paths[..] {
+                [src, dst] => self.mv(src, dst),
+                _ => self.warn(String::from("mv needs a source and a destination. ")),
+            },
+            "pwd" => self.expect_pwd = true,
+            _ => self.warn(format!("Unknown command '{}' ignored. ", cmd)),
+        }
+        Ok(())
+    }
+
+    fn list_entry(&mut self, name: &str, size: Option<usize>) {
+        let dir = match &self.listing {
+            Some(listing) => listing.dir,
+            None => {
+                self.warn(format!("'{}' listed outside of ls. ", name));
+                Some(self.fs.cwd())
+            }
+        };
+        let dir = match dir {
+            Some(dir) => dir,
+            None => return,
+        };
+
+        if let Some(existing) = self.fs.child(dir, name) {
+            let path = self.fs.path(existing);
+            match (&self.fs.node(existing).kind, size) {
+                (NodeKind::File { size: old }, Some(new)) if *old != new => {
+                    self.warn(format!(
+                        "{} was {} bytes, now listed as {}. ",
+                        path, old, new
+                    ));
+                }
+                (NodeKind::File { .. }, None) => {
+                    self.warn(format!("{} was a file, now listed as a directory. ", path));
+                    self.detach(existing);
+                }
+                (NodeKind::Directory { .. }, Some(_)) => {
+                    self.warn(format!("{} was a directory, now listed as a file. ", path));
+                    self.detach(existing);
+                }
+                _ => {}
+            }
+        }
+
+        let added = match size {
+            Some(size) => self.fs.add_file(dir, name, size),
+            None => self.fs.add_dir(dir, name),
+        };
+        match added {
+            Ok(id) => {
+                if let Some(listing) = self.listing.as_mut() {
+                    listing.seen.insert(id);
+                }
+            }
+            Err(err) => self.warn_err(err),
+        }
+    }
+
+    /// Removes anything the last `ls` left out, since the transcript is the latest word.
+    fn finish_listing(&mut self) {
+        if let Some(Listing {
+            dir: Some(dir),
+            seen,
+        }) = self.listing.take()
+        {
+            let missing: Vec<NodeId> = self
+                .fs
+                .children(dir)
+                .iter()
+                .copied()
+                .filter(|child| !seen.contains(child))
+                .collect();
+            for child in missing {
+                let message = format!(
+                    "{} is missing from the listing of {}. ",
+                    self.fs.path(child),
+                    self.fs.path(dir)
+                );
+                self.warn(message);
+                self.detach(child);
+            }
+        }
+    }
+
+    fn mkdir(&mut self, path: &str, parents: bool) {
+        let (parent, name) = split_path(path);
+        let parent = if parents {
+            let mut current = if path.starts_with('/') {
+                self.fs.root()
+            } else {
+                self.fs.cwd()
+            };
+            for segment in parent.split('/').filter(|seg| !seg.is_empty()) {
+                let next = match segment {
+                    "." => Ok(current),
+                    ".." => Ok(self.fs.node(current).parent.unwrap_or(current)),
+                    name => self.fs.add_dir(current, name),
+                };
+                match next {
+                    Ok(id) => current = id,
+                    Err(err) => return self.warn_err(err),
+                }
+            }
+            current
+        } else {
+            match self.fs.resolve(parent) {
+                Ok(id) => id,
+                Err(err) => return self.warn_err(err),
+            }
+        };
+
+        match self.fs.child(parent, name) {
+            Some(id) if parents && self.fs.node(id).is_dir() => {}
+            Some(id) => {
+                let message = format!("mkdir: {} already exists. ", self.fs.path(id));
+                self.warn(message);
+            }
+            None => {
+                if let Err(err) = self.fs.add_dir(parent, name) {
+                    self.warn_err(err);
+                }
+            }
+        }
+    }
+
+    fn rm(&mut self, path: &str, recursive: bool, force: bool) {
+        match self.fs.resolve(path) {
+            Ok(id) if self.fs.node(id).is_dir() && !recursive => {
+                let message = format!("rm: {} is a directory. ", self.fs.path(id));
+                self.warn(message);
+            }
+            Ok(id) if id == self.fs.root() => self.warn(String::from("rm: refusing to remove /. ")),
+            Ok(id) => self.detach(id),
+            Err(_) if force => {}
+            Err(err) => self.warn_err(err),
+        }
+    }
+
+    fn touch(&mut self, path: &str, size: Option<usize>) {
+        match self.fs.resolve(path) {
+            Ok(id) => match (&self.fs.node(id).kind, size) {
+                (NodeKind::File { .. }, Some(size)) => {
+                    let (parent, name) = (self.fs.node(id).parent, self.fs.node(id).name.clone());
+                    if let Err(err) = self.fs.add_file(parent.unwrap_or(0), &name, size) {
+                        self.warn_err(err);
+                    }
+                }
+                (NodeKind::Directory { .. }, Some(_)) => {
+                    let message = format!("touch: {} is a directory. ", self.fs.path(id));
+                    self.warn(message);
+                }
+                (_, None) => {}
+            },
+            Err(_) => {
+                let (parent, name) = split_path(path);
+                let added = self
+                    .fs
+                    .resolve(parent)
+                    .and_then(|dir| self.fs.add_file(dir, name, size.unwrap_or(0)));
+                if let Err(err) = added {
+                    self.warn_err(err);
+                }
+            }
+        }
+    }
+
+    fn mv(&mut self, src: &str, dst: &str) {
+        let src = match self.fs.resolve(src) {
+            Ok(id) => id,
+            Err(err) => return self.warn_err(err),
+        };
+        let (parent, name) = match self.fs.resolve(dst) {
+            Ok(id) if self.fs.node(id).is_dir() => (id, self.fs.node(src).name.clone()),
+            _ => {
+                let (parent, name) = split_path(dst);
+                match self.fs.resolve(parent) {
+                    Ok(id) => (id, name.to_string()),
+                    Err(err) => return self.warn_err(err),
+                }
+            }
+        };
+
+        // Like mv, quietly replace a file that is in the way.
+        if let Some(existing) = self.fs.child(parent, &name) {
+            if existing != src && !self.fs.node(existing).is_dir() && !self.fs.node(src).is_dir() {
+                self.fs.remove(existing).ok();
+            }
+        }
+        if let Err(err) = self.fs.move_node(src, parent, &name) {
+            self.warn_err(err);
+        }
+    }
+
+    /// Removes `id`, leaving the current directory at the removed node's parent if it was
+    /// inside it.
+    fn detach(&mut self, id: NodeId) {
+        let parent = self.fs.node(id).parent;
+        if let Err(err) = self.fs.remove(id) {
+            return self.warn_err(err);
+        }
+        if !self.fs.is_attached(self.fs.cwd()) {
+            let parent = parent.unwrap_or(0);
+            let message = format!(
+                "The current directory was removed, continuing in {}. ",
+                self.fs.path(parent)
+            );
+            self.warn(message);
+            self.fs.cd(&self.fs.path(parent)).ok();
+        }
+    }
+
+    fn warn(&mut self, message: String) {
+        self.warnings.push(Warning {
+            line: self.line,
+            message,
+        });
+    }
 
-    // Check that we have the root node in the first position.
-    let test = tok_vec.pop_front().expect("No tokens in input. ");
-    match test {
-        Token::Command { ref cmd, ref args } => {
-            assert_eq!(cmd, "cd");
-            assert_eq!(args.len(), 1);
-            assert_eq!(args[0], "/");
+    fn warn_err(&mut self, err: Error) {
+        match err {
+            Error::PathError { path, message } => self.warn(format!("{}: {}", path, message)),
+            err => self.warn(format!("{:?}", err)),
+        }
+    }
 
-            TreeItem::from_tokens(args[0].clone(), &mut tok_vec)
+    fn line_error(&self, message: &str) -> Error {
+        Error::ParseError {
+            line: self.line,
+            column: 1,
+            message: message.to_string(),
         }
-        _ => panic!("First item in input is not a command. "),
     }
 }
 
+/// Splits a path into the path of its directory and its final name.
+fn split_path(path: &str) -> (&str, &str) {
+    let trimmed = path.trim_end_matches('/');
+    match trimmed.rsplit_once('/') {
+        Some(("", name)) => ("/", name),
+        Some((parent, name)) => (parent, name),
+        None => (".", trimmed),
+    }
+}
+
+/// Replays a session against an empty filesystem, discarding any warnings. Tokens
+/// correspond one to one with input lines, so errors are reported against the line of the
+/// offending token.
+pub fn parse_tree_from_tokens(tokens: &[Token]) -> Result<FileSystem, Error> {
+    let mut shell = Shell::new();
+    shell.run(tokens)?;
+    Ok(shell.into_fs())
+}
+
 #[cfg(test)]
 mod tests {
-    use super::{parse_tree_from_tokens, tokenise_vec, TreeItem};
+    use super::{
+        human_size, parse_tree_from_tokens, split_words, tokenise_vec, Cleanup, CleanupGoal,
+        CleanupPlan, Deletable, FileSystem, NodeKind, Shell,
+    };
+    use crate::err::Error;
     use crate::io::read_string_col;
     use std::path::Path;
 
+    fn load(path: &str) -> FileSystem {
+        let lines = read_string_col(Path::new(path)).expect("Empty file. ");
+        parse_tree_from_tokens(&tokenise_vec(&lines).unwrap()).expect("Unable to replay session. ")
+    }
+
     #[test]
     pub fn day7_example() {
-        let lines = read_string_col(Path::new("data/day7/example.txt")).expect("Empty file. ");
-        let tokens = tokenise_vec(&lines);
-        let tree = parse_tree_from_tokens(&tokens);
+        let fs = load("data/day7/example.txt");
 
         // Check that the total size is correct.
-        assert_eq!(tree.size(), 48381165);
+        assert_eq!(fs.used_space(), 48381165);
+
+        let small: Vec<(String, usize)> = fs.dirs_where(|_, size| size <= 100_000).collect();
+        assert_eq!(
+            small,
+            vec![(String::from("/a"), 94853), (String::from("/a/e"), 584)]
+        );
+        assert_eq!(
+            fs.dir_sizes().map(|(_, size)| size).collect::<Vec<_>>(),
+            vec![48381165, 94853, 584, 24933642]
+        );
+    }
+
+    #[test]
+    pub fn day7_paths() {
+        let mut fs = load("data/day7/example.txt");
+
+        let i = fs.lookup("/a/e/i").expect("Unable to find /a/e/i. ");
+        assert_eq!(fs.node(i).kind, NodeKind::File { size: 584 });
+        assert_eq!(fs.path(i), "/a/e/i");
+        assert_eq!(fs.path(fs.root()), "/");
+        assert_eq!(fs.lookup("/a/../d/./k"), fs.lookup("/d/k"));
+        assert_eq!(fs.lookup("a/e"), None);
+        assert!(fs.lookup("/a/f/x").is_none());
+
+        fs.cd("/a").unwrap();
+        assert_eq!(fs.resolve("e/i").unwrap(), i);
+        assert_eq!(fs.resolve("../../../d").unwrap(), fs.lookup("/d").unwrap());
+        assert!(matches!(fs.cd("f"), Err(Error::PathError { .. })));
+    }
+
+    #[test]
+    pub fn day7_reports() {
+        let fs = load("data/day7/example.txt");
+
+        assert_eq!(
+            fs.tree(),
+            "/ (48381165)
+├── a/ (94853)
+│   ├── e/ (584)
+│   │   └── i (584)
+│   ├── f (29116)
+│   ├── g (2557)
+│   └── h.lst (62596)
+├── b.txt (14848514)
+├── c.dat (8504156)
+└── d/ (24933642)
+    ├── d.ext (5626152)
+    ├── d.log (8033020)
+    ├── j (4060174)
+    └── k (7214296)
+"
+        );
+        assert_eq!(fs.du(), "47M\t/\n24M\t/d\n93K\t/a\n584\t/a/e\n");
+        assert_eq!(
+            (
+                human_size(1024),
+                human_size(1536),
+                human_size(10 * 1024 - 1)
+            ),
+            (
+                String::from("1.0K"),
+                String::from("1.5K"),
+                String::from("10K")
+            )
+        );
+
+        let json = fs.to_json();
+        assert_eq!(json["size"], 48381165);
+        assert_eq!(json["children"][0]["name"], "a");
+        assert_eq!(
+            json["children"][0]["children"][0]["children"][0]["size"],
+            584
+        );
+        assert_eq!(json["children"][1]["type"], "file");
+    }
+
+    #[test]
+    pub fn day7_repeated_listing() {
+        let session: Vec<String> = [
+            "$ cd /", "$ ls", "dir a", "10 b", "$ cd a", "$ ls", "20 c", "$ cd /", "$ ls", "dir a",
+            "10 b", "$ cd /a", "$ ls", "20 c", "$ cd /",
+        ]
+        .iter()
+        .map(|l| l.to_string())
+        .collect();
+        let mut fs = parse_tree_from_tokens(&tokenise_vec(&session).unwrap()).unwrap();
+
+        assert_eq!(fs.nodes().len(), 4);
+        assert_eq!(fs.used_space(), 30);
+        assert_eq!(fs.cwd(), fs.root());
+
+        // Sizes are cached, but a change to the tree is still seen.
+        let a = fs.lookup("/a").unwrap();
+        fs.add_file(a, "d", 5).unwrap();
+        assert_eq!((fs.used_space(), fs.size(a)), (35, 25));
+
+        // A cd into a directory that was never listed creates it, with a warning.
+        let unlisted: Vec<String> = ["$ cd /", "$ cd missing"]
+            .iter()
+            .map(|l| l.to_string())
+            .collect();
+        let fs = parse_tree_from_tokens(&tokenise_vec(&unlisted).unwrap()).unwrap();
+        assert_eq!(fs.path(fs.cwd()), "/missing");
+        assert_eq!(
+            fs.lookup("/missing").map(|id| fs.node(id).is_dir()),
+            Some(true)
+        );
+        let shell = replay(&["$ cd /", "$ cd missing"]);
+        let lines: Vec<usize> = shell.warnings().iter().map(|w| w.line).collect();
+        assert_eq!(lines, vec![2]);
+    }
+
+    #[test]
+    pub fn day7_size_cache() {
+        fn recompute(fs: &FileSystem, id: usize) -> usize {
+            match &fs.node(id).kind {
+                NodeKind::File { size } => *size,
+                NodeKind::Directory { children } => {
+                    children.iter().map(|c| recompute(fs, *c)).sum()
+                }
+            }
+        }
+        let check = |fs: &FileSystem| {
+            for id in fs.descendants(fs.root()) {
+                assert_eq!(fs.size(id), recompute(fs, id), "{}", fs.path(id));
+            }
+        };
+
+        let mut fs = load("data/day7/data.txt");
+        check(&fs);
+
+        let mut example = load("data/day7/example.txt");
+        check(&example);
+        let (a, d) = (example.lookup("/a").unwrap(), example.lookup("/d").unwrap());
+        let e = example.lookup("/a/e").unwrap();
+
+        // Each change must invalidate the sizes cached by the previous check.
+        example.add_file(e, "new", 1000).unwrap();
+        check(&example);
+        assert_eq!(example.size(e), 1584);
+        example.add_file(e, "i", 16).unwrap();
+        check(&example);
+        assert_eq!(example.size(a), 94853 - 584 + 1016);
 
-        let found_dirs = tree.find_dirs_smaller_than(100_000);
-        let found_dirs_sum: usize = found_dirs.into_iter().map(TreeItem::size).sum();
-        assert_eq!(found_dirs_sum, 94853 + 584);
+        example.move_node(e, d, "e").unwrap();
+        check(&example);
+        assert_eq!(example.size(a), 94853 - 584);
+        assert_eq!(example.size(d), 24933642 + 1016);
+
+        example.remove(d).unwrap();
+        check(&example);
+        assert_eq!(example.used_space(), 48381165 - 584 - 24933642);
+
+        // The predicate sees each directory's path as well as its size.
+        let under_a: Vec<(String, usize)> = example
+            .dirs_where(|path, size| path.starts_with("/a") && size > 0)
+            .collect();
+        assert_eq!(under_a, vec![(String::from("/a"), 94853 - 584)]);
+
+        let root = fs.root();
+        fs.add_file(root, "big", 1).unwrap();
+        check(&fs);
     }
 
     #[test]
     pub fn day7_part1() {
-        let lines = read_string_col(Path::new("data/day7/data.txt")).expect("Empty file. ");
-        let tokens = tokenise_vec(&lines);
-        let tree = parse_tree_from_tokens(&tokens);
+        let fs = load("data/day7/data.txt");
 
-        let found_dirs = tree.find_dirs_smaller_than(100_000);
-        let found_dirs_sum: usize = found_dirs.into_iter().map(TreeItem::size).sum();
+        let found_dirs_sum: usize = fs.dirs_where(|_, size| size <= 100_000).map(|d| d.1).sum();
         assert_eq!(found_dirs_sum, 2104783);
     }
 
     #[test]
     pub fn day7_part2() {
-        const TOTAL_AVAILABLE_SPACE: usize = 70_000_000;
-        const REQUIERED_SPACE: usize = 30_000_000;
+        let fs = load("data/day7/data.txt");
+        let cleanup = Cleanup {
+            goal: CleanupGoal::FewestDeletions,
+            ..Default::default()
+        };
+        let plan = cleanup.plan(&fs).unwrap().expect("No results found. ");
+
+        assert_eq!(plan.deletions.len(), 1);
+        assert_eq!(plan.freed, 5883165);
+    }
+
+    fn replay(session: &[&str]) -> Shell {
+        let lines: Vec<String> = session.iter().map(|l| l.to_string()).collect();
+        let mut shell = Shell::new();
+        shell.run(&tokenise_vec(&lines).unwrap()).unwrap();
+        shell
+    }
+
+    #[test]
+    pub fn day7_shell_commands() {
+        assert_eq!(
+            split_words(r#"mv "my dir"/a\ b 'c d' e"#).unwrap(),
+            vec!["mv", "my dir/a b", "c d", "e"]
+        );
+        assert!(split_words("cd 'oops").is_err());
+
+        let shell = replay(&[
+            "$ cd /",
+            "$ mkdir -p 'my docs/old' tmp",
+            "$ touch 100 'my docs/a file.txt' tmp/x",
+            "$ cd my\\ docs",
+            "$ pwd",
+            "/my docs",
+            "$ ls",
+            "dir old",
+            "100 a file.txt",
+            "$ mv 'a file.txt' old/renamed",
+            "$ mv /tmp/x old",
+            "$ rm -r /tmp",
+            "$ touch 5 old/x",
+        ]);
+        let fs = shell.fs();
+        assert_eq!(shell.warnings(), &[]);
+        assert!(fs.lookup("/tmp").is_none());
+        assert_eq!(
+            fs.lookup("/my docs/old/renamed").map(|id| fs.size(id)),
+            Some(100)
+        );
+        assert_eq!(fs.lookup("/my docs/old/x").map(|id| fs.size(id)), Some(5));
+        assert_eq!(fs.used_space(), 105);
+        assert_eq!(fs.path(fs.cwd()), "/my docs");
+    }
+
+    #[test]
+    pub fn day7_shell_warnings() {
+        let shell = replay(&[
+            "$ ls",
+            "dir a",
+            "10 b",
+            "20 c",
+            "$ ls",
+            "dir a",
+            "15 b",
+            "$ cd a",
+            "$ pwd",
+            "/b",
+            "$ rm /a",
+            "$ rm -r /a",
+            "$ mkdir /missing/x",
+            "$ mv /b /b/inside",
+            "$ frobnicate",
+        ]);
+        let lines: Vec<usize> = shell.warnings().iter().map(|w| w.line).collect();
+        assert_eq!(lines, vec![7, 8, 10, 11, 12, 13, 14, 15]);
+        assert!(shell.warnings()[0].message.contains("/b was 10 bytes"));
+        assert!(shell.warnings()[1].message.contains("/c is missing"));
+        assert_eq!(shell.fs().used_space(), 15);
+        assert_eq!(shell.fs().cwd(), shell.fs().root());
+
+        // Relisting the directory holding the current one as a file moves the shell out of
+        // it, and a cd to a directory that was never listed creates it.
+        let shell = replay(&[
+            "$ cd /",
+            "$ ls",
+            "dir a",
+            "$ cd a",
+            "$ ls",
+            "dir b",
+            "$ cd b",
+            "$ mkdir c",
+            "$ cd c",
+            "$ ls /a",
+            "5 b",
+            "$ cd missing/deep",
+            "$ pwd",
+            "/a/missing/deep",
+        ]);
+        let lines: Vec<usize> = shell.warnings().iter().map(|w| w.line).collect();
+        assert_eq!(lines, vec![11, 11, 12]);
+        assert!(shell.warnings()[1].message.contains("continuing in /a"));
+        assert!(shell.warnings()[2].message.contains("never listed"));
+        let fs = shell.fs();
+        assert!(fs.is_attached(fs.cwd()));
+        assert_eq!(fs.path(fs.cwd()), "/a/missing/deep");
+        assert_eq!(
+            fs.lookup("/a/b").map(|id| fs.node(id).is_dir()),
+            Some(false)
+        );
+
+        let bad: Vec<String> = ["$ ls", "dir a", "oops"]
+            .iter()
+            .map(|l| l.to_string())
+            .collect();
+        match tokenise_vec(&bad) {
+            Err(Error::ParseError { line, .. }) => assert_eq!(line, 3),
+            other => panic!("Expected a parse error, got {:?}", other),
+        }
+    }
+
+    /// The best plan by trying every set of nodes, as (deletions, bytes).
+    fn brute_force_plan(fs: &FileSystem, cleanup: &Cleanup) -> Option<(usize, usize)> {
+        let needed = cleanup.space_needed(fs);
+        let candidates: Vec<usize> = (1..fs.nodes().len())
+            .filter(|id| match cleanup.deletable {
+                Deletable::Directories => fs.node(*id).is_dir(),
+                Deletable::Files => !fs.node(*id).is_dir(),
+                Deletable::Both => true,
+            })
+            .collect();
+        (0..1_usize << candidates.len())
+            .filter_map(|mask| {
+                let chosen: Vec<usize> = (0..candidates.len())
+                    .filter(|i| mask >> i & 1 == 1)
+                    .map(|i| candidates[i])
+                    .collect();
+                let nested = chosen
+                    .iter()
+                    .any(|a| chosen.iter().any(|b| a != b && fs.is_within(*a, *b)));
+                let freed: usize = chosen.iter().map(|id| fs.size(*id)).sum();
+                (!nested && freed >= needed).then_some((chosen.len(), freed))
+            })
+            .min_by_key(|&(count, freed)| match cleanup.goal {
+                CleanupGoal::FewestDeletions => (count, freed),
+                CleanupGoal::FewestBytes => (0, freed),
+            })
+    }
+
+    #[test]
+    pub fn day7_cleanup_example() {
+        let fs = load("data/day7/example.txt");
+
+        assert_eq!(
+            Cleanup::default().plan(&fs).unwrap(),
+            Some(CleanupPlan {
+                deletions: vec![(String::from("/d"), 24933642)],
+                freed: 24933642
+            })
+        );
+        let already_free = Cleanup {
+            target_free: 1000,
+            ..Default::default()
+        };
+        assert_eq!(already_free.plan(&fs).unwrap().unwrap().deletions, vec![]);
+
+        // A real disk can need far more bytes freed than the planner will track.
+        let mut disk = FileSystem::new();
+        let root = disk.root();
+        let data = disk.add_dir(root, "data").unwrap();
+        disk.add_file(data, "blob", 1 << 40).unwrap();
+        let huge = Cleanup {
+            capacity: 1 << 41,
+            target_free: (1 << 41) - (1 << 39),
+            ..Default::default()
+        };
+        assert!(matches!(huge.plan(&disk), Err(Error::InvalidInput(_))));
+        let capped = Cleanup {
+            max_states: 1000,
+            ..Default::default()
+        };
+        assert!(matches!(capped.plan(&fs), Err(Error::InvalidInput(_))));
 
-        let lines = read_string_col(Path::new("data/day7/data.txt")).expect("Empty file. ");
-        let tokens = tokenise_vec(&lines);
-        let tree = parse_tree_from_tokens(&tokens);
-        let used_space = tree.size();
-        let currently_remaining_space = TOTAL_AVAILABLE_SPACE - used_space;
+        // Check every combination against brute force, on the example with its file sizes
+        // cut down a thousandfold so that each plan is quick.
+        let lines: Vec<String> = read_string_col(Path::new("data/day7/example.txt"))
+            .expect("Empty file. ")
+            .into_iter()
+            .map(|line| match line.split_once(' ') {
+                Some((size, name)) if size.parse::<usize>().is_ok() => {
+                    format!("{} {}", size.parse::<usize>().unwrap().div_ceil(1000), name)
+                }
+                _ => line,
+            })
+            .collect();
+        let fs = parse_tree_from_tokens(&tokenise_vec(&lines).unwrap()).unwrap();
+        for target_free in [20_000, 30_000, 40_000] {
+            for goal in [CleanupGoal::FewestBytes, CleanupGoal::FewestDeletions] {
+                for deletable in [Deletable::Directories, Deletable::Files, Deletable::Both] {
+                    let cleanup = Cleanup {
+                        capacity: 70_000,
+                        target_free,
+                        goal,
+                        deletable,
+                        ..Default::default()
+                    };
+                    let plan = cleanup.plan(&fs).unwrap();
+                    let expected = brute_force_plan(&fs, &cleanup);
+                    let found = plan.as_ref().map(|p| (p.deletions.len(), p.freed));
+                    match goal {
+                        CleanupGoal::FewestDeletions => {
+                            assert_eq!(found, expected, "{:?}", cleanup)
+                        }
+                        CleanupGoal::FewestBytes => {
+                            assert_eq!(found.map(|f| f.1), expected.map(|e| e.1), "{:?}", cleanup)
+                        }
+                    }
+                    if let Some(plan) = plan {
+                        let total: usize = plan.deletions.iter().map(|d| d.1).sum();
+                        assert_eq!(total, plan.freed);
+                    }
+                }
+            }
+        }
+    }
 
-        let found_dirs = tree.find_dirs_larger_than(REQUIERED_SPACE - currently_remaining_space);
-        let mut found_dirs_size: Vec<usize> = found_dirs.into_iter().map(TreeItem::size).collect();
-        found_dirs_size.sort();
-        let result = found_dirs_size.first().expect("No results found. ");
+    #[test]
+    pub fn day7_cleanup() {
+        let fs = load("data/day7/data.txt");
+        let cleanup = Cleanup {
+            deletable: Deletable::Both,
+            ..Default::default()
+        };
+        let needed = cleanup.space_needed(&fs);
+        let plan = cleanup.plan(&fs).unwrap().expect("No plan found. ");
 
-        assert_eq!(*result, 5883165);
+        assert!(plan.freed >= needed && plan.freed < 5883165);
+        for (a, _) in plan.deletions.iter() {
+            for (b, _) in plan.deletions.iter() {
+                assert!(a == b || !b.starts_with(&format!("{}/", a)));
+            }
+        }
     }
 }
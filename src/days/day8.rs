@@ -1,117 +1,408 @@
+use std::collections::HashSet;
+use std::io::Write;
+
 use crate::err::Error;
+use crate::image;
+use crate::math::gcd;
 
 pub struct TreeMap {
     pub heights: Vec<Vec<usize>>,
 }
 
 impl TreeMap {
-    pub fn from_string_col(col: &Vec<String>) -> Result<TreeMap, Error> {
-        let heights = col
-            .iter()
-            .map(|row| {
-                row.chars()
-                    .map(|inchar| {
-                        inchar
-                            .to_string()
-                            .parse::<usize>()
-                            .expect("Unable to parse char. ")
-                    })
-                    .collect()
-            })
-            .collect();
+    /// Parses one row of the map per line. A row may be a run of single digits, as in the
+    /// puzzle, or heights of any size separated by whitespace or commas. Blank lines are
+    /// skipped, and every row must have the same number of trees.
+    pub fn from_string_col(col: &[String]) -> Result<TreeMap, Error> {
+        let mut heights: Vec<Vec<usize>> = vec![];
+        for (line_no, row) in col.iter().enumerate() {
+            if row.trim().is_empty() {
+                continue;
+            }
+
+            let parse_error = |column: usize, message: String| Error::ParseError {
+                line: line_no + 1,
+                column,
+                message,
+            };
+            let cells = split_cells(row);
+            let mut parsed = Vec::with_capacity(cells.len());
+            for (offset, cell) in cells.iter() {
+                if cell.is_empty() {
+                    return Err(parse_error(
+                        offset + 1,
+                        String::from("Missing tree height. "),
+                    ));
+                }
+                let height = cell.parse::<usize>().map_err(|_| {
+                    parse_error(offset + 1, format!("Unable to parse height '{}'. ", cell))
+                })?;
+                parsed.push(height);
+            }
+
+            if let Some(first) = heights.first() {
+                if parsed.len() != first.len() {
+                    return Err(parse_error(
+                        1,
+                        format!("Expected {} trees, found {}. ", first.len(), parsed.len()),
+                    ));
+                }
+            }
+            heights.push(parsed);
+        }
 
         Ok(Self { heights })
     }
 
+    /// The number of rows in the map.
+    pub fn height(&self) -> usize {
+        self.heights.len()
+    }
+
+    /// The number of columns in the map, taken from its first row.
+    pub fn width(&self) -> usize {
+        self.heights.first().map_or(0, |row| row.len())
+    }
+
     pub fn on_boundary(&self, i: usize, j: usize) -> bool {
-        if i == 0
-            || j == 0
-            || i == self.heights.len() - 1
-            || j == self
-                .heights
-                .first()
-                .expect("Unable to find first row. ")
-                .len()
-                - 1
-        {
-            true
-        } else {
-            false
-        }
+        i == 0 || j == 0 || i == self.height() - 1 || j == self.width() - 1
     }
 
     /// Finds out whether the tree at row i and column j is visible from the
-    /// outside of the map.
+    /// outside of the map. This checks a single tree; use [`TreeMap::survey`] for the
+    /// whole map.
     pub fn is_visible(&self, i: usize, j: usize) -> bool {
         let tree = self.heights[i][j];
-        let row = self.heights[i].clone();
-        let col: Vec<usize> = self.heights.iter().map(|row| row[j].clone()).collect();
-
-        if self.on_boundary(i, j)
-            || tree > row[0..j].iter().fold(usize::MIN, |a, &b| a.max(b))
-            || tree > row[j + 1..].iter().fold(usize::MIN, |a, &b| a.max(b))
-            || tree > col[0..i].iter().fold(usize::MIN, |a, &b| a.max(b))
-            || tree > col[i + 1..].iter().fold(usize::MIN, |a, &b| a.max(b))
-        {
-            true
-        } else {
-            false
-        }
+        let row = &self.heights[i];
+
+        self.on_boundary(i, j)
+            || row[0..j].iter().all(|t| *t < tree)
+            || row[j + 1..].iter().all(|t| *t < tree)
+            || self.heights[0..i].iter().all(|row| row[j] < tree)
+            || self.heights[i + 1..].iter().all(|row| row[j] < tree)
     }
 
     pub fn count_visible(&self) -> usize {
-        let mut total = 0;
-        for i in 0..self.heights.len() {
-            for j in 0..self
-                .heights
-                .first()
-                .expect("Unable to find first row. ")
-                .len()
-            {
-                total += if self.is_visible(i, j) { 1 } else { 0 };
-            }
-        }
-        total
+        self.survey().count_visible()
     }
 
+    /// The product of the viewing distances from the tree at row i and column j. This
+    /// checks a single tree; use [`TreeMap::survey`] for the whole map.
     pub fn scenic_score(&self, i: usize, j: usize) -> usize {
         let tree = self.heights[i][j];
-        let row = self.heights[i].clone();
-        let col: Vec<usize> = self.heights.iter().map(|row| row[j].clone()).collect();
+        let row = &self.heights[i];
+        let distance = |trees: Vec<usize>| {
+            trees
+                .iter()
+                .position(|t| *t >= tree)
+                .map_or(trees.len(), |val| val + 1)
+        };
+
+        let left_dist = distance(row[0..j].iter().rev().copied().collect());
+        let right_dist = distance(row[j + 1..].to_vec());
+        let up_dist = distance(self.heights[0..i].iter().rev().map(|row| row[j]).collect());
+        let down_dist = distance(self.heights[i + 1..].iter().map(|row| row[j]).collect());
+
+        left_dist * right_dist * down_dist * up_dist
+    }
+
+    /// Finds the visibility and scenic score of every tree at once. Each row and column is
+    /// swept from both ends, keeping a stack of the trees that could still block the view,
+    /// so the whole map takes time proportional to its size.
+    pub fn survey(&self) -> Survey {
+        let (height, width) = (self.height(), self.width());
+        let mut visible = vec![vec![false; width]; height];
+        let mut scenic = vec![vec![1; width]; height];
+
+        let rows = (0..height).map(|i| (0..width).map(|j| (i, j)).collect::<Vec<_>>());
+        let cols = (0..width).map(|j| (0..height).map(|i| (i, j)).collect::<Vec<_>>());
+        for line in rows.chain(cols) {
+            for sweep in [line.clone(), line.into_iter().rev().collect()] {
+                // The position and height of each tree not yet hidden behind a taller one.
+                let mut stack: Vec<(usize, usize)> = vec![];
+                for (pos, &(i, j)) in sweep.iter().enumerate() {
+                    let tree = self.heights[i][j];
+                    while stack.last().is_some_and(|&(_, t)| t < tree) {
+                        stack.pop();
+                    }
+                    match stack.last() {
+                        None => {
+                            visible[i][j] = true;
+                            scenic[i][j] *= pos;
+                        }
+                        Some(&(blocker, _)) => scenic[i][j] *= pos - blocker,
+                    }
+                    stack.push((pos, tree));
+                }
+            }
+        }
+
+        Survey { visible, scenic }
+    }
+}
+
+/// The visibility and scenic score of every tree in a [`TreeMap`], indexed as `[row][column]`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Survey {
+    pub visible: Vec<Vec<bool>>,
+    pub scenic: Vec<Vec<usize>>,
+}
 
-        let left: Vec<usize> = row[0..j].into_iter().map(|v| *v).collect();
-        let right: Vec<usize> = row[j + 1..].into_iter().map(|v| *v).collect();
-        let up: Vec<usize> = col[0..i].into_iter().map(|v| *v).collect();
-        let down: Vec<usize> = col[i + 1..].into_iter().map(|v| *v).collect();
+impl Survey {
+    pub fn count_visible(&self) -> usize {
+        self.visible.iter().flatten().filter(|v| **v).count()
+    }
+
+    pub fn max_scenic_score(&self) -> usize {
+        self.scenic.iter().flatten().copied().max().unwrap_or(0)
+    }
 
-        let left_dist = left
+    /// Shows the visible trees as `#` and hidden ones as `.`, with each row ended by a newline.
+    pub fn mask(&self) -> String {
+        self.visible
             .iter()
-            .rev()
-            .position(|t| *t >= tree)
-            .map_or(left.len(), |val| val + 1);
-        let right_dist = right
+            .map(|row| {
+                row.iter()
+                    .map(|v| if *v { '#' } else { '.' })
+                    .chain(['\n'])
+                    .collect::<String>()
+            })
+            .collect()
+    }
+
+    /// The `n` trees with the highest scenic scores, as `((row, column), score)`, best first.
+    /// Ties are broken by position.
+    pub fn top_scenic(&self, n: usize) -> Vec<((usize, usize), usize)> {
+        let mut cells: Vec<((usize, usize), usize)> = self
+            .scenic
             .iter()
-            .position(|t| *t >= tree)
-            .map_or(right.len(), |val| val + 1);
-        let up_dist = up
+            .enumerate()
+            .flat_map(|(i, row)| row.iter().enumerate().map(move |(j, s)| ((i, j), *s)))
+            .collect();
+        cells.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        cells.truncate(n);
+        cells
+    }
+}
+
+/// A property of each tree that can be drawn as a heatmap.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Layer {
+    Height,
+    Visibility,
+    /// Scenic scores on a logarithmic scale, since a few trees score far above the rest.
+    Scenic,
+}
+
+impl TreeMap {
+    /// Colours each tree by `layer`, from black for the lowest value through red and yellow
+    /// to white for the highest, returning the pixels in row-major order. Returns an error if
+    /// the rows are not all the same length.
+    pub fn heatmap(&self, layer: Layer) -> Result<Vec<[u8; 3]>, Error> {
+        self.check_rectangular()?;
+        let survey = self.survey();
+        let values: Vec<f64> = match layer {
+            Layer::Height => self.heights.iter().flatten().map(|h| *h as f64).collect(),
+            Layer::Visibility => survey
+                .visible
+                .iter()
+                .flatten()
+                .map(|v| *v as u8 as f64)
+                .collect(),
+            Layer::Scenic => survey
+                .scenic
+                .iter()
+                .flatten()
+                .map(|s| (*s as f64).ln_1p())
+                .collect(),
+        };
+
+        let lo = values.iter().copied().fold(f64::INFINITY, f64::min);
+        let hi = values.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+        Ok(values
             .iter()
-            .rev()
-            .position(|t| *t >= tree)
-            .map_or(up.len(), |val| val + 1);
-        let down_dist = down
+            .map(|v| {
+                let t = if hi > lo { (v - lo) / (hi - lo) } else { 1.0 };
+                let channel = |start: f64| ((t * 3.0 - start).clamp(0.0, 1.0) * 255.0) as u8;
+                [channel(0.0), channel(1.0), channel(2.0)]
+            })
+            .collect())
+    }
+
+    /// Writes the heatmap for `layer` as a plain PPM image, one pixel per tree.
+    pub fn write_ppm<W: Write>(&self, layer: Layer, out: &mut W) -> Result<(), Error> {
+        let pixels = self.heatmap(layer)?;
+        Ok(image::write_ppm(self.width(), self.height(), &pixels, out)?)
+    }
+
+    /// Writes the heatmap for `layer` as a PNG image, one pixel per tree.
+    pub fn write_png<W: Write>(&self, layer: Layer, out: &mut W) -> Result<(), Error> {
+        let pixels = self.heatmap(layer)?;
+        Ok(image::write_png(self.width(), self.height(), &pixels, out)?)
+    }
+
+    /// Checks that every row has as many trees as the first, as [`TreeMap::from_string_col`]
+    /// ensures but `heights` alone does not.
+    fn check_rectangular(&self) -> Result<(), Error> {
+        match self
+            .heights
+            .iter()
+            .position(|row| row.len() != self.width())
+        {
+            Some(i) => Err(Error::ParseError {
+                line: i + 1,
+                column: 1,
+                message: format!(
+                    "Expected {} trees, found {}. ",
+                    self.width(),
+                    self.heights[i].len()
+                ),
+            }),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Splits a row into its cells, each with the byte offset it starts at.
+fn split_cells(row: &str) -> Vec<(usize, &str)> {
+    if row.contains(',') {
+        let mut offset = 0;
+        row.split(',')
+            .map(|cell| {
+                let start = offset + cell.len() - cell.trim_start().len();
+                offset += cell.len() + 1;
+                (start, cell.trim())
+            })
+            .collect()
+    } else if row.trim().contains(char::is_whitespace) {
+        let mut cells = vec![];
+        let mut start = None;
+        for (i, c) in row.char_indices().chain([(row.len(), ' ')]) {
+            match (start, c.is_whitespace()) {
+                (None, false) => start = Some(i),
+                (Some(s), true) => {
+                    cells.push((s, &row[s..i]));
+                    start = None;
+                }
+                _ => {}
+            }
+        }
+        cells
+    } else {
+        let trimmed = row.trim_start();
+        let lead = row.len() - trimmed.len();
+        trimmed
+            .trim_end()
+            .char_indices()
+            .map(|(i, c)| (lead + i, &trimmed[i..i + c.len_utf8()]))
+            .collect()
+    }
+}
+
+/// One of the eight directions a viewer can look along, as `(row, column)` steps.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compass {
+    N,
+    NE,
+    E,
+    SE,
+    S,
+    SW,
+    W,
+    NW,
+}
+
+impl Compass {
+    pub const ALL: [Compass; 8] = [
+        Compass::N,
+        Compass::NE,
+        Compass::E,
+        Compass::SE,
+        Compass::S,
+        Compass::SW,
+        Compass::W,
+        Compass::NW,
+    ];
+
+    pub fn step(&self) -> (i64, i64) {
+        match self {
+            Self::N => (-1, 0),
+            Self::NE => (-1, 1),
+            Self::E => (0, 1),
+            Self::SE => (1, 1),
+            Self::S => (1, 0),
+            Self::SW => (1, -1),
+            Self::W => (0, -1),
+            Self::NW => (-1, -1),
+        }
+    }
+}
+
+impl TreeMap {
+    /// The trees a viewer at row i and column j, with their eye at height `eye`, can see when
+    /// looking along `(d_row, d_col)`, nearest first. Any rational direction can be given;
+    /// only the trees lying exactly on the line are considered. A tree is seen if its top
+    /// rises above the line of sight to every nearer tree.
+    pub fn line_of_sight(
+        &self,
+        (i, j): (usize, usize),
+        eye: usize,
+        (d_row, d_col): (i64, i64),
+    ) -> Vec<(usize, usize)> {
+        assert!((d_row, d_col) != (0, 0), "Direction must be non-zero. ");
+        let g = gcd(d_row.abs(), d_col.abs());
+        let (d_row, d_col) = (d_row / g, d_col / g);
+
+        let mut seen = vec![];
+        // The steepest sightline so far, as a rise over a number of steps.
+        let mut steepest: Option<(i64, i64)> = None;
+        for k in 1_i64.. {
+            let (row, col) = (i as i64 + k * d_row, j as i64 + k * d_col);
+            if row < 0 || col < 0 || row >= self.height() as i64 || col >= self.width() as i64 {
+                break;
+            }
+            let (row, col) = (row as usize, col as usize);
+            let rise = self.heights[row][col] as i64 - eye as i64;
+            let visible = match steepest {
+                Some((top, steps)) => rise * steps > top * k,
+                None => true,
+            };
+            if visible {
+                seen.push((row, col));
+                steepest = Some((rise, k));
+            }
+        }
+        seen
+    }
+
+    /// Every tree visible from row i and column j along any of `directions`.
+    pub fn visible_from(
+        &self,
+        from: (usize, usize),
+        eye: usize,
+        directions: &[(i64, i64)],
+    ) -> HashSet<(usize, usize)> {
+        directions
             .iter()
-            .position(|t| *t >= tree)
-            .map_or(down.len(), |val| val + 1);
+            .flat_map(|dir| self.line_of_sight(from, eye, *dir))
+            .collect()
+    }
 
-        left_dist * right_dist * down_dist * up_dist
+    /// Every tree visible from row i and column j along the eight compass directions.
+    pub fn visible_from_compass(
+        &self,
+        from: (usize, usize),
+        eye: usize,
+    ) -> HashSet<(usize, usize)> {
+        let directions: Vec<(i64, i64)> = Compass::ALL.iter().map(|c| c.step()).collect();
+        self.visible_from(from, eye, &directions)
     }
 }
 
 #[cfg(test)]
 pub mod tests {
-    use itertools::Itertools;
-
-    use super::TreeMap;
+    use super::{Compass, Layer, TreeMap};
+    use crate::err::Error;
     use crate::io;
     use std::path::Path;
 
@@ -141,20 +432,8 @@ pub mod tests {
             .expect("Unable to find input file. ");
         let map = TreeMap::from_string_col(&input).expect("Unable to create tree map from input. ");
 
-        let scores: Vec<usize> = (0..map.heights.len())
-            .map(|i| {
-                (0..map
-                    .heights
-                    .first()
-                    .expect("Unable to find first row. ")
-                    .len())
-                    .map(|j| map.scenic_score(i, j))
-                    .collect::<Vec<usize>>()
-            })
-            .concat();
-
-        let max_score = scores.iter().fold(usize::MIN, |a, &b| a.max(b));
-        assert_eq!(max_score, 8);
+        assert_eq!(map.scenic_score(3, 2), 8);
+        assert_eq!(map.survey().max_scenic_score(), 8);
     }
 
     #[test]
@@ -163,19 +442,137 @@ pub mod tests {
             .expect("Unable to find input file. ");
         let map = TreeMap::from_string_col(&input).expect("Unable to create tree map from input. ");
 
-        let scores: Vec<usize> = (0..map.heights.len())
-            .map(|i| {
-                (0..map
-                    .heights
-                    .first()
-                    .expect("Unable to find first row. ")
-                    .len())
-                    .map(|j| map.scenic_score(i, j))
-                    .collect::<Vec<usize>>()
-            })
-            .concat();
+        assert_eq!(map.survey().max_scenic_score(), 315495);
+    }
+
+    #[test]
+    fn day8_survey_matches_per_cell() {
+        let input = io::read_string_col(Path::new("data/day8/data.txt"))
+            .expect("Unable to find input file. ");
+        let mut maps = vec![TreeMap::from_string_col(&input).unwrap()];
+        // Irregular shapes and runs of equal heights.
+        for rows in [
+            vec!["5"],
+            vec!["12321"],
+            vec!["3", "3", "3"],
+            vec!["2552", "5115", "2552"],
+        ] {
+            let rows: Vec<String> = rows.into_iter().map(String::from).collect();
+            maps.push(TreeMap::from_string_col(&rows).unwrap());
+        }
+
+        for map in maps {
+            let survey = map.survey();
+            for i in 0..map.height() {
+                for j in 0..map.width() {
+                    assert_eq!(survey.visible[i][j], map.is_visible(i, j), "({}, {})", i, j);
+                    assert_eq!(
+                        survey.scenic[i][j],
+                        map.scenic_score(i, j),
+                        "({}, {})",
+                        i,
+                        j
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn day8_heatmaps() {
+        let input = io::read_string_col(Path::new("data/day8/example.txt"))
+            .expect("Unable to find input file. ");
+        let map = TreeMap::from_string_col(&input).expect("Unable to create tree map from input. ");
+        let survey = map.survey();
 
-        let max_score = scores.iter().fold(usize::MIN, |a, &b| a.max(b));
-        assert_eq!(max_score, 315495);
+        assert_eq!(survey.mask(), "#####\n###.#\n##.##\n#.#.#\n#####\n");
+        assert_eq!(survey.top_scenic(2), vec![((3, 2), 8), ((2, 1), 6)]);
+
+        let heights = map.heatmap(Layer::Height).unwrap();
+        assert_eq!(heights[1], [0, 0, 0]);
+        assert_eq!(heights[19], [255, 255, 255]);
+        let visibility = map.heatmap(Layer::Visibility).unwrap();
+        assert_eq!((visibility[0], visibility[8]), ([255, 255, 255], [0, 0, 0]));
+        assert_eq!(map.heatmap(Layer::Scenic).unwrap()[17], [255, 255, 255]);
+
+        let mut ppm = vec![];
+        map.write_ppm(Layer::Height, &mut ppm).unwrap();
+        assert!(ppm.starts_with(b"P3\n5 5\n255\n"));
+        let mut png = vec![];
+        map.write_png(Layer::Scenic, &mut png).unwrap();
+        assert!(png.starts_with(b"\x89PNG"));
+
+        // The heights can be edited directly, so a jagged map is an error rather than a panic.
+        let jagged = TreeMap {
+            heights: vec![vec![3, 0, 3], vec![2, 5], vec![6, 5, 3]],
+        };
+        assert!(matches!(
+            jagged.heatmap(Layer::Scenic),
+            Err(Error::ParseError { line: 2, .. })
+        ));
+        let mut ppm = vec![];
+        assert!(jagged.write_ppm(Layer::Height, &mut ppm).is_err());
+        assert!(ppm.is_empty());
+    }
+
+    #[test]
+    fn day8_line_of_sight() {
+        let input = io::read_string_col(Path::new("data/day8/example.txt"))
+            .expect("Unable to find input file. ");
+        let map = TreeMap::from_string_col(&input).expect("Unable to create tree map from input. ");
+
+        assert_eq!(
+            map.line_of_sight((0, 0), 3, Compass::E.step()),
+            vec![(0, 1), (0, 2), (0, 3)]
+        );
+        // Scaled directions land on the same trees.
+        assert_eq!(map.line_of_sight((0, 0), 0, (2, 4)), vec![(1, 2)]);
+        assert_eq!(
+            map.line_of_sight((0, 0), 9, (2, 4)),
+            map.line_of_sight((0, 0), 9, (1, 2))
+        );
+        // The tree at the far corner is hidden below the sightline over the one before it.
+        assert_eq!(
+            map.line_of_sight((4, 4), 9, Compass::NW.step()),
+            vec![(3, 3), (2, 2), (1, 1)]
+        );
+
+        // Looking from high enough, every tree along the eight directions can be seen.
+        let seen = map.visible_from_compass((2, 2), 100);
+        assert_eq!(seen.len(), 16);
+        assert!(!seen.contains(&(2, 2)) && !seen.contains(&(0, 1)));
+        let seen = map.visible_from((2, 2), 0, &[(0, 1), (-1, 2)]);
+        assert_eq!(seen, [(2, 3), (1, 4)].into_iter().collect());
+    }
+
+    #[test]
+    fn day8_parse_formats() {
+        let parse = |rows: &[&str]| {
+            let rows: Vec<String> = rows.iter().map(|r| String::from(*r)).collect();
+            TreeMap::from_string_col(&rows)
+        };
+
+        let digits = parse(&["303", "255"]).unwrap();
+        let spaced = parse(&["3  0 3", " 2\t5 5 ", ""]).unwrap();
+        let csv = parse(&["3,0,3", "2, 5,5"]).unwrap();
+        assert_eq!(digits.heights, vec![vec![3, 0, 3], vec![2, 5, 5]]);
+        assert_eq!(spaced.heights, digits.heights);
+        assert_eq!(csv.heights, digits.heights);
+
+        let big = parse(&["1200 15 980", "40 2210 7"]).unwrap();
+        assert_eq!(big.heights[1], vec![40, 2210, 7]);
+        assert_eq!(big.survey().count_visible(), 6);
+
+        for (rows, at) in [
+            (vec!["303", "2x5"], (2, 2)),
+            (vec!["3 0 3", "2 5 -1"], (2, 5)),
+            (vec!["3,0,3", "2,,5"], (2, 3)),
+            (vec!["3,0,3", "2,5"], (2, 1)),
+        ] {
+            match parse(&rows) {
+                Err(Error::ParseError { line, column, .. }) => assert_eq!((line, column), at),
+                other => panic!("Expected a parse error, got {:?}", other.map(|m| m.heights)),
+            }
+        }
     }
 }
@@ -1,200 +1,893 @@
-use nalgebra::Vector2;
+use nalgebra::Vector3;
+use std::collections::HashSet;
+use std::io::Write;
+use std::str::FromStr;
 
-type Real = f64;
-type Vec2 = Vector2<Real>;
+use crate::err::Error;
+use crate::image;
 
-#[derive(Debug, Clone)]
+/// A grid cell, as `(x, y)` with `y` increasing upwards.
+pub type Point = (i32, i32);
+
+/// A cell in three dimensions, as `(x, y, z)`.
+pub type Point3 = Vector3<i32>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Direction {
     Up,
     Right,
     Down,
     Left,
+    Forward,
+    Back,
 }
 
 impl Direction {
-    pub fn unit_vector(&self) -> Vec2 {
+    /// The step taken in the plane. `Forward` and `Back` lie outside the plane, so they leave
+    /// a 2D rope where it is.
+    pub fn unit_vector(&self) -> Point {
+        let v = self.unit_vector_3d();
+        (v.x, v.y)
+    }
+
+    pub fn unit_vector_3d(&self) -> Point3 {
         match self {
-            Self::Up => Vec2::new(0.0, 1.0),
-            Self::Right => Vec2::new(1.0, 0.0),
-            Self::Down => Vec2::new(0.0, -1.0),
-            Self::Left => Vec2::new(-1.0, 0.0),
+            Self::Up => Point3::new(0, 1, 0),
+            Self::Right => Point3::new(1, 0, 0),
+            Self::Down => Point3::new(0, -1, 0),
+            Self::Left => Point3::new(-1, 0, 0),
+            Self::Forward => Point3::new(0, 0, 1),
+            Self::Back => Point3::new(0, 0, -1),
         }
     }
 }
 
-impl From<String> for Direction {
-    fn from(instr: String) -> Self {
-        match instr.as_str() {
-            "U" => Self::Up,
-            "R" => Self::Right,
-            "D" => Self::Down,
-            "L" => Self::Left,
-            _ => panic!("Invalid direction in parse. "),
+impl FromStr for Direction {
+    type Err = Error;
+
+    fn from_str(instr: &str) -> Result<Self, Self::Err> {
+        match instr {
+            "U" => Ok(Self::Up),
+            "R" => Ok(Self::Right),
+            "D" => Ok(Self::Down),
+            "L" => Ok(Self::Left),
+            "F" => Ok(Self::Forward),
+            "B" => Ok(Self::Back),
+            _ => Err(Error::ParseError {
+                line: 1,
+                column: 1,
+                message: format!("Invalid direction '{}'. ", instr),
+            }),
         }
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct Motion {
-    dir: Direction,
-    steps: usize,
+    pub dir: Direction,
+    pub steps: usize,
+}
+
+impl FromStr for Motion {
+    type Err = Error;
+
+    /// Parses a motion such as `R 4`. Errors are reported against line 1.
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        let (dir, steps) = input.trim().split_once(' ').ok_or(Error::ParseError {
+            line: 1,
+            column: 1,
+            message: format!("Expected a direction and a step count, found '{}'. ", input),
+        })?;
+        let steps = steps
+            .trim()
+            .parse::<usize>()
+            .map_err(|_| Error::ParseError {
+                line: 1,
+                column: dir.len() + 2,
+                message: format!("Unable to parse step count '{}'. ", steps.trim()),
+            })?;
+        Ok(Motion {
+            dir: dir.parse()?,
+            steps,
+        })
+    }
 }
 
 impl Motion {
-    pub fn from_str(input: &str) -> Motion {
-        let segs: Vec<&str> = input.split(" ").collect();
-        let dir = Direction::from(segs[0].to_string());
-        let steps = segs[1].parse::<usize>().expect("Unable to parse input. ");
-        Motion { dir, steps }
+    /// Moves the head of the rope one cell at a time, letting the knots follow after each step.
+    pub fn move_head(&self, rope: &mut Rope) {
+        let (dx, dy) = self.dir.unit_vector();
+        for _ in 0..self.steps {
+            rope.head = (rope.head.0 + dx, rope.head.1 + dy);
+            rope.follow_head();
+        }
     }
 
-    pub fn move_head(&self, rope: &mut Rope, grid: &mut Grid) {
-        let unit_vec = self.dir.unit_vector();
+    pub fn move_head_3d(&self, rope: &mut Rope3) {
+        let unit = self.dir.unit_vector_3d();
         for _ in 0..self.steps {
-            rope.head += unit_vec;
+            rope.head += unit;
             rope.follow_head();
+        }
+    }
+}
 
-            // Now check to see if head position is already in visited heads.
-            if !grid.visited_head.contains(&rope.head) {
-                grid.visited_head.push(rope.head.clone());
-            }
+/// How a knot steps towards the knot ahead of it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum StepRule {
+    /// Move one cell along every axis that differs, as in the puzzle.
+    #[default]
+    Diagonal,
+    /// Move one cell along the axis with the largest gap, the first such axis on a tie.
+    Orthogonal,
+}
+
+/// When a knot sees the knot ahead of it move.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum UpdateOrder {
+    /// Knots move front to back, each following where its leader has just moved to.
+    #[default]
+    Eager,
+    /// Every knot follows where its leader was before the step, so a pull travels down the
+    /// rope one knot per step.
+    Lazy,
+}
+
+/// The physics of a rope. The default is the puzzle's rule.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FollowRule {
+    pub step: StepRule,
+    /// How far, on any one axis, a knot may fall behind before it moves.
+    pub slack: i32,
+    pub update: UpdateOrder,
+}
+
+impl Default for FollowRule {
+    fn default() -> Self {
+        FollowRule {
+            step: StepRule::Diagonal,
+            slack: 1,
+            update: UpdateOrder::Eager,
+        }
+    }
+}
 
-            // Now check the visited tails to see if the location already exists.
-            let last_knot = rope.knots.last().expect("No knots in the rope. ");
-            if !grid.visited_tail.contains(&last_knot) {
-                grid.visited_tail.push(last_knot.clone());
+impl FollowRule {
+    /// The step a knot takes to close the gap `diff` to the knot ahead of it, which is zero
+    /// while it is within the slack.
+    pub fn follow<const N: usize>(&self, diff: [i32; N]) -> [i32; N] {
+        let mut step = [0; N];
+        if diff.iter().all(|d| d.abs() <= self.slack) {
+            return step;
+        }
+        match self.step {
+            StepRule::Diagonal => {
+                for (s, d) in step.iter_mut().zip(diff.iter()) {
+                    *s = d.signum();
+                }
+            }
+            StepRule::Orthogonal => {
+                let axis = (0..N).fold(0, |best, i| {
+                    if diff[i].abs() > diff[best].abs() {
+                        i
+                    } else {
+                        best
+                    }
+                });
+                step[axis] = diff[axis].signum();
             }
         }
+        step
+    }
+
+    /// Moves each of `knots` towards the one ahead of it, the first following `head`.
+    fn pull<P: Knot>(&self, head: P, knots: &mut [P]) {
+        let leaders: Vec<P> = match self.update {
+            UpdateOrder::Eager => vec![],
+            UpdateOrder::Lazy => knots.to_vec(),
+        };
+        let mut leader = head;
+        for (i, knot) in knots.iter_mut().enumerate() {
+            *knot = knot.step_towards(leader, self);
+            leader = match self.update {
+                UpdateOrder::Eager => *knot,
+                UpdateOrder::Lazy => leaders[i],
+            };
+        }
+    }
+}
+
+/// A point that a knot can occupy.
+trait Knot: Copy {
+    fn step_towards(self, leader: Self, rule: &FollowRule) -> Self;
+}
+
+impl Knot for Point {
+    fn step_towards(self, leader: Self, rule: &FollowRule) -> Self {
+        let [dx, dy] = rule.follow([leader.0 - self.0, leader.1 - self.1]);
+        (self.0 + dx, self.1 + dy)
+    }
+}
+
+impl Knot for Point3 {
+    fn step_towards(self, leader: Self, rule: &FollowRule) -> Self {
+        let diff: [i32; 3] = (leader - self).into();
+        self + Point3::from(rule.follow(diff))
     }
 }
 
-#[derive(Default)]
+/// The smallest box, in `(x, y)` coordinates, that contains a set of cells.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BoundingBox {
+    pub min: Point,
+    pub max: Point,
+}
+
+impl BoundingBox {
+    fn of<'a>(points: impl Iterator<Item = &'a Point>) -> Option<BoundingBox> {
+        points.fold(None, |acc, &(x, y)| {
+            Some(match acc {
+                None => BoundingBox {
+                    min: (x, y),
+                    max: (x, y),
+                },
+                Some(bb) => BoundingBox {
+                    min: (bb.min.0.min(x), bb.min.1.min(y)),
+                    max: (bb.max.0.max(x), bb.max.1.max(y)),
+                },
+            })
+        })
+    }
+}
+
+/// A rope whose head is pulled around by motions, and whose knots follow it. Every cell
+/// occupied by every knot is recorded, with the head as knot 0 and `knots[i]` as knot `i + 1`.
+#[derive(Debug, Clone)]
 pub struct Rope {
-    pub head: Vec2,
-    pub knots: Vec<Vec2>,
+    pub head: Point,
+    knots: Vec<Point>,
+    rule: FollowRule,
+    visited: Vec<HashSet<Point>>,
 }
 
 impl Rope {
+    /// Creates a rope with `n_knots` knots behind the head, all starting at the origin.
     pub fn new(n_knots: usize) -> Self {
-        Rope {
-            knots: (0..n_knots)
-                .into_iter()
-                .map(|_| Vec2::new(0.0, 0.0))
-                .collect(),
-            ..Default::default()
+        let mut rope = Rope {
+            head: (0, 0),
+            knots: vec![(0, 0); n_knots],
+            rule: FollowRule::default(),
+            visited: vec![HashSet::new(); n_knots + 1],
+        };
+        rope.record_visits();
+        rope
+    }
+
+    pub fn with_rule(mut self, rule: FollowRule) -> Self {
+        self.rule = rule;
+        self
+    }
+
+    pub fn rule(&self) -> FollowRule {
+        self.rule
+    }
+
+    /// The single step a knot takes to close the gap `diff` to the knot ahead of it.
+    pub fn diff_to_motion(diff: Point) -> Point {
+        (diff.0.signum(), diff.1.signum())
+    }
+
+    /// Moves each knot towards the one ahead of it, according to the rope's rule.
+    pub fn follow_head(&mut self) {
+        self.rule.pull(self.head, &mut self.knots);
+        self.record_visits();
+    }
+
+    /// The knots behind the head, nearest first.
+    pub fn knots(&self) -> &[Point] {
+        &self.knots
+    }
+
+    /// The number of knots, including the head.
+    pub fn n_knots(&self) -> usize {
+        self.knots.len() + 1
+    }
+
+    /// The position of knot `k`, where knot 0 is the head.
+    pub fn knot(&self, k: usize) -> Point {
+        match k {
+            0 => self.head,
+            _ => self.knots[k - 1],
         }
     }
 
-    pub fn diff_to_motion(diff: Vec2) -> Vec2 {
-        diff.map(|comp| {
-            if comp == 0.0 {
-                0.0
-            } else if comp > 0.0 {
-                1.0
+    /// The last knot of the rope, which is the head if there are no other knots.
+    pub fn tail(&self) -> Point {
+        *self.knots.last().unwrap_or(&self.head)
+    }
+
+    /// Every cell visited by knot `k`, where knot 0 is the head.
+    pub fn visited(&self, k: usize) -> &HashSet<Point> {
+        &self.visited[k]
+    }
+
+    pub fn visited_head(&self) -> &HashSet<Point> {
+        self.visited(0)
+    }
+
+    pub fn visited_tail(&self) -> &HashSet<Point> {
+        self.visited(self.knots.len())
+    }
+
+    /// The number of distinct cells visited by knot `k`.
+    pub fn unique_visits(&self, k: usize) -> usize {
+        self.visited[k].len()
+    }
+
+    pub fn unique_tail_visits(&self) -> usize {
+        self.visited_tail().len()
+    }
+
+    /// The furthest knot `k` has been from the origin, by Manhattan distance.
+    pub fn max_distance(&self, k: usize) -> i32 {
+        self.visited[k]
+            .iter()
+            .map(|(x, y)| x.abs() + y.abs())
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// The bounding box of every cell visited by knot `k`.
+    pub fn bounds(&self, k: usize) -> BoundingBox {
+        BoundingBox::of(self.visited[k].iter()).expect("Every knot visits the origin. ")
+    }
+
+    /// The bounding box of every cell visited by any knot.
+    pub fn total_bounds(&self) -> BoundingBox {
+        BoundingBox::of(self.visited.iter().flatten()).expect("Every knot visits the origin. ")
+    }
+
+    /// Draws the rope within `bounds`, in the style of the puzzle text. Each cell shows the
+    /// frontmost knot on it, `H` for the head and `1` to `9` for the knots behind it, or `s`
+    /// for the starting cell. Knots past the ninth are drawn as `+`.
+    pub fn render(&self, bounds: &BoundingBox) -> String {
+        let knots: Vec<Point> = (0..self.n_knots()).map(|k| self.knot(k)).collect();
+        render_knots(bounds, &knots)
+    }
+
+    /// Draws every cell visited by knot `k` as `#`, with the starting cell as `s`, on a grid
+    /// just large enough to hold the trail.
+    pub fn render_trail(&self, k: usize) -> String {
+        render_cells(&self.bounds(k), |cell| {
+            if cell == (0, 0) {
+                's'
+            } else if self.visited[k].contains(&cell) {
+                '#'
             } else {
-                -1.0
+                '.'
             }
         })
     }
 
+    fn record_visits(&mut self) {
+        self.visited[0].insert(self.head);
+        for (visited, knot) in self.visited[1..].iter_mut().zip(self.knots.iter()) {
+            visited.insert(*knot);
+        }
+    }
+}
+
+/// A rope in three dimensions, moved by all six directions.
+#[derive(Debug, Clone)]
+pub struct Rope3 {
+    pub head: Point3,
+    knots: Vec<Point3>,
+    rule: FollowRule,
+    visited: Vec<HashSet<Point3>>,
+}
+
+impl Rope3 {
+    /// Creates a rope with `n_knots` knots behind the head, all starting at the origin.
+    pub fn new(n_knots: usize) -> Self {
+        let mut rope = Rope3 {
+            head: Point3::zeros(),
+            knots: vec![Point3::zeros(); n_knots],
+            rule: FollowRule::default(),
+            visited: vec![HashSet::new(); n_knots + 1],
+        };
+        rope.record_visits();
+        rope
+    }
+
+    pub fn with_rule(mut self, rule: FollowRule) -> Self {
+        self.rule = rule;
+        self
+    }
+
+    pub fn rule(&self) -> FollowRule {
+        self.rule
+    }
+
     pub fn follow_head(&mut self) {
-        // First move the first knot forward.
-        let diff = self.head - self.knots[0];
-        if diff.norm() > 2.0_f64.sqrt() {
-            let translate = Self::diff_to_motion(diff);
-            self.knots[0] += translate;
-        }
-
-        // Now move the remaining tail elements forward, if that exists.
-        for i in 1..self.knots.len() {
-            let diff = self.knots[i - 1] - self.knots[i];
-            if diff.norm() > 2.0_f64.sqrt() {
-                let translate = Self::diff_to_motion(diff);
-                self.knots[i] += translate;
-            }
+        self.rule.pull(self.head, &mut self.knots);
+        self.record_visits();
+    }
+
+    /// The knots behind the head, nearest first.
+    pub fn knots(&self) -> &[Point3] {
+        &self.knots
+    }
+
+    pub fn tail(&self) -> Point3 {
+        *self.knots.last().unwrap_or(&self.head)
+    }
+
+    /// Every cell visited by knot `k`, where knot 0 is the head.
+    pub fn visited(&self, k: usize) -> &HashSet<Point3> {
+        &self.visited[k]
+    }
+
+    pub fn unique_visits(&self, k: usize) -> usize {
+        self.visited[k].len()
+    }
+
+    pub fn unique_tail_visits(&self) -> usize {
+        self.unique_visits(self.knots.len())
+    }
+
+    fn record_visits(&mut self) {
+        self.visited[0].insert(self.head);
+        for (visited, knot) in self.visited[1..].iter_mut().zip(self.knots.iter()) {
+            visited.insert(*knot);
         }
     }
 }
 
-pub fn parse_motions(in_vec: &Vec<String>) -> Vec<Motion> {
-    in_vec
-        .into_iter()
-        .map(|string| Motion::from_str(string))
+/// Draws one character per cell of `bounds`, with the top row first and each row ended by
+/// a newline.
+fn render_cells<F: Fn(Point) -> char>(bounds: &BoundingBox, cell: F) -> String {
+    (bounds.min.1..=bounds.max.1)
+        .rev()
+        .map(|y| {
+            (bounds.min.0..=bounds.max.0)
+                .map(|x| cell((x, y)))
+                .chain(['\n'])
+                .collect::<String>()
+        })
         .collect()
 }
 
-pub struct Grid {
-    width: usize,
-    height: usize,
-    pub visited_head: Vec<Vec2>,
-    pub visited_tail: Vec<Vec2>,
+/// Draws knots, given head first, as [`Rope::render`] does.
+fn render_knots(bounds: &BoundingBox, knots: &[Point]) -> String {
+    render_cells(bounds, |cell| {
+        match knots.iter().position(|knot| *knot == cell) {
+            Some(0) => 'H',
+            Some(k) => char::from_digit(k as u32, 10).unwrap_or('+'),
+            None if cell == (0, 0) => 's',
+            None => '.',
+        }
+    })
+}
+
+/// How often an [`Animation`] captures the rope.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrameRate {
+    PerMotion,
+    PerStep,
+}
+
+/// One frame of an [`Animation`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame {
+    /// The position of every knot, head first.
+    pub knots: Vec<Point>,
+    /// The cells the tail reached for the first time since the previous frame.
+    pub new_tail_cells: Vec<Point>,
+}
+
+/// A recording of a rope following a series of motions. Every frame is drawn on the same
+/// grid, sized to fit every cell that any knot visits.
+#[derive(Debug, Clone)]
+pub struct Animation {
+    frames: Vec<Frame>,
+    bounds: BoundingBox,
 }
 
-impl Grid {
-    pub fn new(height: usize, width: usize) -> Self {
-        Self {
-            width,
-            height,
-            visited_head: vec![],
-            visited_tail: vec![],
+impl Animation {
+    /// Records a rope with `n_knots` knots behind the head, starting with its initial state.
+    pub fn record(motions: &[Motion], n_knots: usize, rate: FrameRate) -> Animation {
+        let mut rope = Rope::new(n_knots);
+        let mut new_tail_cells = vec![rope.tail()];
+        let mut frames = vec![];
+        let mut capture = |rope: &Rope, new_tail_cells: &mut Vec<Point>| {
+            frames.push(Frame {
+                knots: (0..rope.n_knots()).map(|k| rope.knot(k)).collect(),
+                new_tail_cells: std::mem::take(new_tail_cells),
+            })
+        };
+        capture(&rope, &mut new_tail_cells);
+
+        for m in motions {
+            for _ in 0..m.steps {
+                let visits = rope.unique_tail_visits();
+                Motion { steps: 1, ..*m }.move_head(&mut rope);
+                if rope.unique_tail_visits() > visits {
+                    new_tail_cells.push(rope.tail());
+                }
+                if rate == FrameRate::PerStep {
+                    capture(&rope, &mut new_tail_cells);
+                }
+            }
+            if rate == FrameRate::PerMotion {
+                capture(&rope, &mut new_tail_cells);
+            }
+        }
+        Animation {
+            bounds: rope.total_bounds(),
+            frames,
         }
     }
 
-    pub fn unique_tail_visits(&self) -> usize {
-        let tmp = self.visited_tail.clone();
-        tmp.len()
+    pub fn frames(&self) -> &[Frame] {
+        &self.frames
     }
+
+    pub fn bounds(&self) -> BoundingBox {
+        self.bounds
+    }
+
+    pub fn text_frames(&self) -> Vec<String> {
+        self.frames
+            .iter()
+            .map(|frame| render_knots(&self.bounds, &frame.knots))
+            .collect()
+    }
+
+    /// Writes every frame as text, separated by blank lines.
+    pub fn write_text<W: Write>(&self, out: &mut W) -> std::io::Result<()> {
+        for (i, frame) in self.text_frames().iter().enumerate() {
+            if i > 0 {
+                writeln!(out)?;
+            }
+            write!(out, "{}", frame)?;
+        }
+        Ok(())
+    }
+
+    /// Writes the animation as a looping GIF, with each cell drawn as a `scale` pixel square
+    /// and each frame shown for `delay` hundredths of a second. The trail left by the tail so
+    /// far is drawn beneath the rope. Returns an `InvalidInput` error if the scaled image is
+    /// too large for a GIF.
+    pub fn write_gif<W: Write>(
+        &self,
+        scale: usize,
+        delay: u16,
+        out: &mut W,
+    ) -> std::io::Result<()> {
+        const PALETTE: [[u8; 3]; 5] = [
+            [255, 255, 255],
+            [180, 180, 180],
+            [0, 160, 0],
+            [200, 0, 0],
+            [40, 40, 40],
+        ];
+        let (min, max) = (self.bounds.min, self.bounds.max);
+        let width = (max.0 - min.0 + 1) as usize;
+        let height = (max.1 - min.1 + 1) as usize;
+        // Check the size before drawing any frames, which could be very large.
+        image::gif_dimension(width.saturating_mul(scale))?;
+        image::gif_dimension(height.saturating_mul(scale))?;
+
+        let index = |(x, y): Point| (max.1 - y) as usize * width + (x - min.0) as usize;
+        // The tail's trail so far, built up frame by frame.
+        let mut trail = vec![0_u8; width * height];
+        let frames: Vec<Vec<u8>> = self
+            .frames
+            .iter()
+            .map(|frame| {
+                for cell in frame.new_tail_cells.iter() {
+                    trail[index(*cell)] = 1;
+                }
+                let mut cells = trail.clone();
+                cells[index((0, 0))] = 2;
+                for (k, knot) in frame.knots.iter().enumerate().rev() {
+                    cells[index(*knot)] = if k == 0 { 3 } else { 4 };
+                }
+                (0..height * scale)
+                    .flat_map(|row| {
+                        let cells = &cells;
+                        (0..width * scale).map(move |col| cells[row / scale * width + col / scale])
+                    })
+                    .collect()
+            })
+            .collect();
+        image::write_gif(width * scale, height * scale, &PALETTE, &frames, delay, out)
+    }
+}
+
+/// Answers "how many cells does the tail visit" for several rope lengths at once, where each
+/// length counts the head. A knot only ever follows the knot ahead of it, so every shorter
+/// rope is a prefix of the longest one, and a single simulation covers them all.
+pub fn unique_tail_visits_for(motions: &[Motion], lengths: &[usize]) -> Vec<usize> {
+    let longest = lengths.iter().copied().max().unwrap_or(1).max(1);
+    let mut rope = Rope::new(longest - 1);
+    for m in motions {
+        m.move_head(&mut rope);
+    }
+    lengths
+        .iter()
+        .map(|len| rope.unique_visits(len.saturating_sub(1)))
+        .collect()
+}
+
+/// Parses one motion per line, reporting errors against the line they occur on.
+pub fn parse_motions(in_vec: &[String]) -> Result<Vec<Motion>, Error> {
+    in_vec
+        .iter()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(line_no, line)| {
+            line.parse::<Motion>().map_err(|err| match err {
+                Error::ParseError {
+                    column, message, ..
+                } => Error::ParseError {
+                    line: line_no + 1,
+                    column,
+                    message,
+                },
+                err => err,
+            })
+        })
+        .collect()
 }
 
 #[cfg(test)]
 mod tests {
+    use crate::err::Error;
     use crate::io;
+    use std::collections::HashSet;
     use std::path::Path;
 
-    use super::{parse_motions, Grid, Rope};
+    use super::{
+        parse_motions, unique_tail_visits_for, Animation, BoundingBox, Direction, FollowRule,
+        FrameRate, Motion, Point, Point3, Rope, Rope3, StepRule, UpdateOrder,
+    };
+
+    #[test]
+    pub fn day9_parse() {
+        assert_eq!(
+            "R 4".parse::<Motion>().unwrap(),
+            Motion {
+                dir: Direction::Right,
+                steps: 4
+            }
+        );
+        let input = vec![String::from("U 2"), String::from("X 3")];
+        match parse_motions(&input) {
+            Err(Error::ParseError { line, column, .. }) => assert_eq!((line, column), (2, 1)),
+            other => panic!("Expected a parse error, got {:?}", other),
+        }
+    }
 
     #[test]
     pub fn day9_example() {
         let input =
             io::read_string_col(Path::new("data/day9/example.txt")).expect("No lines in input. ");
-        let motions = parse_motions(&input);
+        let motions = parse_motions(&input).expect("Unable to parse motions. ");
         let mut rope = Rope::new(1);
-        let mut grid = Grid::new(5, 6);
 
         for m in motions {
-            m.move_head(&mut rope, &mut grid);
+            m.move_head(&mut rope);
         }
 
-        assert_eq!(grid.unique_tail_visits(), 13);
+        assert_eq!(rope.unique_tail_visits(), 13);
+        assert_eq!(rope.head, (2, 2));
+        assert_eq!(rope.tail(), (1, 2));
     }
 
     #[test]
     pub fn day9_part1() {
         let input =
             io::read_string_col(Path::new("data/day9/data.txt")).expect("No lines in input. ");
-        let motions = parse_motions(&input);
+        let motions = parse_motions(&input).expect("Unable to parse motions. ");
         let mut rope = Rope::new(1);
-        let mut grid = Grid::new(5, 6);
 
         for m in motions {
-            m.move_head(&mut rope, &mut grid);
+            m.move_head(&mut rope);
         }
 
-        assert_eq!(grid.unique_tail_visits(), 6284);
+        // The answer provided by AOC.
+        assert_eq!(rope.unique_tail_visits(), 6284);
     }
 
     #[test]
     pub fn day9_part2() {
         let input =
             io::read_string_col(Path::new("data/day9/data.txt")).expect("No lines in input. ");
-        let motions = parse_motions(&input);
+        let motions = parse_motions(&input).expect("Unable to parse motions. ");
         let mut rope = Rope::new(9);
-        let mut grid = Grid::new(5, 6);
 
         for m in motions {
-            m.move_head(&mut rope, &mut grid);
+            m.move_head(&mut rope);
         }
 
-        assert_eq!(grid.unique_tail_visits(), 2661);
+        // The answer provided by AOC.
+        assert_eq!(rope.unique_tail_visits(), 2661);
+    }
+
+    #[test]
+    pub fn day9_knot_stats() {
+        let input =
+            io::read_string_col(Path::new("data/day9/example.txt")).expect("No lines in input. ");
+        let motions = parse_motions(&input).expect("Unable to parse motions. ");
+        let mut rope = Rope::new(9);
+
+        for m in motions.iter() {
+            m.move_head(&mut rope);
+        }
+
+        assert_eq!(rope.n_knots(), 10);
+        assert_eq!(rope.unique_visits(1), 13);
+        assert_eq!(rope.unique_tail_visits(), 1);
+        assert_eq!(rope.max_distance(0), 8);
+        assert_eq!(
+            rope.bounds(0),
+            BoundingBox {
+                min: (0, 0),
+                max: (5, 4)
+            }
+        );
+        assert_eq!(rope.total_bounds(), rope.bounds(0));
+        assert_eq!(unique_tail_visits_for(&motions, &[2, 10]), vec![13, 1]);
+    }
+
+    #[test]
+    pub fn day9_many_lengths() {
+        let input =
+            io::read_string_col(Path::new("data/day9/data.txt")).expect("No lines in input. ");
+        let motions = parse_motions(&input).expect("Unable to parse motions. ");
+        let visits = unique_tail_visits_for(&motions, &[2, 10, 50]);
+
+        assert_eq!(&visits[..2], &[6284, 2661]);
+        let mut rope = Rope::new(49);
+        for m in motions {
+            m.move_head(&mut rope);
+        }
+        assert_eq!(visits[2], rope.unique_tail_visits());
+    }
+
+    #[test]
+    pub fn day9_render() {
+        let input =
+            io::read_string_col(Path::new("data/day9/example.txt")).expect("No lines in input. ");
+        let motions = parse_motions(&input).expect("Unable to parse motions. ");
+
+        let mut rope = Rope::new(1);
+        for m in motions.iter() {
+            m.move_head(&mut rope);
+        }
+        assert_eq!(rope.render_trail(1), "..##.\n...##\n.####\n....#\ns###.\n");
+
+        let animation = Animation::record(&motions, 9, FrameRate::PerMotion);
+        let frames = animation.text_frames();
+        assert_eq!(frames.len(), motions.len() + 1);
+        assert_eq!(frames[0], "......\n......\n......\n......\nH.....\n");
+        assert_eq!(frames[1], "......\n......\n......\n......\n4321H.\n");
+        assert_eq!(
+            frames.last().unwrap(),
+            "......\n......\n.1H3..\n.5....\n6.....\n"
+        );
+
+        let steps = Animation::record(&motions, 9, FrameRate::PerStep);
+        assert_eq!(steps.frames().len(), 25);
+        assert_eq!(steps.bounds(), animation.bounds());
+
+        // Frames hold only positions, and the tail's trail is rebuilt from what each adds.
+        for n_knots in [1, 9] {
+            let mut rope = Rope::new(n_knots);
+            for m in motions.iter() {
+                m.move_head(&mut rope);
+            }
+            for rate in [FrameRate::PerMotion, FrameRate::PerStep] {
+                let recording = Animation::record(&motions, n_knots, rate);
+                let trail: HashSet<Point> = recording
+                    .frames()
+                    .iter()
+                    .flat_map(|frame| frame.new_tail_cells.iter().copied())
+                    .collect();
+                assert_eq!(&trail, rope.visited_tail());
+                assert_eq!(
+                    recording.frames().last().unwrap().knots,
+                    (0..rope.n_knots())
+                        .map(|k| rope.knot(k))
+                        .collect::<Vec<_>>()
+                );
+            }
+        }
+
+        let mut text = vec![];
+        animation.write_text(&mut text).unwrap();
+        assert_eq!(String::from_utf8(text).unwrap().split("\n\n").count(), 9);
+        let mut gif = vec![];
+        steps.write_gif(4, 10, &mut gif).unwrap();
+        assert!(gif.starts_with(b"GIF89a\x18\x00\x14\x00"));
+
+        // A trail wider than a GIF allows is an error rather than a truncated image.
+        let long = Animation::record(
+            &parse_motions(&[String::from("R 70000")]).unwrap(),
+            1,
+            FrameRate::PerMotion,
+        );
+        let mut gif = vec![];
+        let err = long.write_gif(1, 10, &mut gif).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+        assert!(gif.is_empty());
+    }
+
+    #[test]
+    pub fn day9_follow_rules() {
+        let right = |steps| Motion {
+            dir: Direction::Right,
+            steps,
+        };
+        let follow = |rule: FollowRule, n_knots: usize, motions: &[Motion]| {
+            let mut rope = Rope::new(n_knots).with_rule(rule);
+            for m in motions {
+                m.move_head(&mut rope);
+            }
+            rope.knots().to_vec()
+        };
+
+        let slack = FollowRule {
+            slack: 2,
+            ..Default::default()
+        };
+        assert_eq!(follow(slack, 1, &[right(3)]), vec![(1, 0)]);
+
+        let lazy = FollowRule {
+            update: UpdateOrder::Lazy,
+            ..Default::default()
+        };
+        assert_eq!(
+            follow(FollowRule::default(), 3, &[right(4)]),
+            vec![(3, 0), (2, 0), (1, 0)]
+        );
+        assert_eq!(follow(lazy, 3, &[right(4)]), vec![(3, 0), (1, 0), (0, 0)]);
+
+        let orthogonal = FollowRule {
+            step: StepRule::Orthogonal,
+            ..Default::default()
+        };
+        let up = Motion {
+            dir: Direction::Up,
+            steps: 2,
+        };
+        assert_eq!(
+            follow(FollowRule::default(), 1, &[right(1), up]),
+            vec![(1, 1)]
+        );
+        assert_eq!(follow(orthogonal, 1, &[right(1), up]), vec![(0, 1)]);
+    }
+
+    #[test]
+    pub fn day9_rope_3d() {
+        let input =
+            io::read_string_col(Path::new("data/day9/data.txt")).expect("No lines in input. ");
+        let motions = parse_motions(&input).expect("Unable to parse motions. ");
+        let mut rope = Rope3::new(9);
+        for m in motions.iter() {
+            m.move_head_3d(&mut rope);
+        }
+        // A rope that never leaves the plane behaves exactly like a 2D one.
+        assert_eq!(rope.unique_tail_visits(), 2661);
+
+        let motions = parse_motions(&[String::from("F 3"), String::from("U 2")]).unwrap();
+        let mut rope = Rope3::new(1);
+        for m in motions.iter() {
+            m.move_head_3d(&mut rope);
+        }
+        assert_eq!(rope.head, Point3::new(0, 2, 3));
+        assert_eq!(rope.tail(), Point3::new(0, 1, 3));
+        assert_eq!(rope.unique_tail_visits(), 4);
     }
 }
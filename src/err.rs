@@ -3,6 +3,32 @@ pub enum Error {
     IOError(std::io::Error),
     RegexError(regex::Error),
     ParseIntError(std::num::ParseIntError),
+    /// A problem with the puzzle input, located by its 1-based line and column.
+    ParseError {
+        line: usize,
+        column: usize,
+        message: String,
+    },
+    /// A problem within a named block of the puzzle input, such as `Monkey 3`.
+    BlockError {
+        block: String,
+        line: usize,
+        message: String,
+    },
+    /// A glyph in a rendered image that does not match any known letter.
+    UnknownGlyph {
+        index: usize,
+        glyph: String,
+    },
+    /// A numeric result did not fit in the chosen representation.
+    Overflow(String),
+    /// A well-formed value that cannot be used, such as a zero divisor.
+    InvalidInput(String),
+    /// A path that cannot be followed in a filesystem model.
+    PathError {
+        path: String,
+        message: String,
+    },
 }
 
 impl From<std::io::Error> for Error {
@@ -0,0 +1,273 @@
+//! Minimal writers for the Netpbm, PNG and GIF image formats, so that puzzle state can be
+//! viewed without pulling in an imaging crate. PNG and GIF data is stored uncompressed.
+
+use std::io::Write;
+
+/// Writes a bitmap, indexed as `bitmap[row][column]`, as a plain PBM (`P1`) image. Set
+/// pixels are drawn in black.
+pub fn write_pbm<W: Write>(bitmap: &[Vec<bool>], out: &mut W) -> std::io::Result<()> {
+    let width = bitmap.iter().map(|row| row.len()).max().unwrap_or(0);
+    writeln!(out, "P1\n{} {}", width, bitmap.len())?;
+    for row in bitmap {
+        let line: Vec<&str> = (0..width)
+            .map(|col| match row.get(col) {
+                Some(true) => "1",
+                _ => "0",
+            })
+            .collect();
+        writeln!(out, "{}", line.join(" "))?;
+    }
+    Ok(())
+}
+
+/// Writes an RGB image as a plain PPM (`P3`) image. `pixels` holds `width * height` colours
+/// in row-major order.
+pub fn write_ppm<W: Write>(
+    width: usize,
+    height: usize,
+    pixels: &[[u8; 3]],
+    out: &mut W,
+) -> std::io::Result<()> {
+    assert_eq!(
+        pixels.len(),
+        width * height,
+        "Pixel count does not match size. "
+    );
+    writeln!(out, "P3\n{} {}\n255", width, height)?;
+    for row in pixels.chunks(width.max(1)) {
+        let line: Vec<String> = row
+            .iter()
+            .map(|[r, g, b]| format!("{} {} {}", r, g, b))
+            .collect();
+        writeln!(out, "{}", line.join("  "))?;
+    }
+    Ok(())
+}
+
+/// Writes a bitmap as a black-and-white PNG image, with set pixels drawn in black.
+pub fn write_png_bitmap<W: Write>(bitmap: &[Vec<bool>], out: &mut W) -> std::io::Result<()> {
+    let width = bitmap.iter().map(|row| row.len()).max().unwrap_or(0);
+    let pixels: Vec<[u8; 3]> = bitmap
+        .iter()
+        .flat_map(|row| {
+            (0..width).map(move |col| match row.get(col) {
+                Some(true) => [0, 0, 0],
+                _ => [255, 255, 255],
+            })
+        })
+        .collect();
+    write_png(width, bitmap.len(), &pixels, out)
+}
+
+/// Writes an RGB image as a PNG. `pixels` holds `width * height` colours in row-major order.
+pub fn write_png<W: Write>(
+    width: usize,
+    height: usize,
+    pixels: &[[u8; 3]],
+    out: &mut W,
+) -> std::io::Result<()> {
+    assert_eq!(
+        pixels.len(),
+        width * height,
+        "Pixel count does not match size. "
+    );
+    out.write_all(&[0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n'])?;
+
+    let mut header = vec![];
+    header.extend((width as u32).to_be_bytes());
+    header.extend((height as u32).to_be_bytes());
+    // 8 bits per channel, RGB, default compression and filtering, no interlacing.
+    header.extend([8, 2, 0, 0, 0]);
+    write_png_chunk(b"IHDR", &header, out)?;
+
+    // Each scanline is prefixed with filter type 0 (none).
+    let mut raw = Vec::with_capacity(height * (width * 3 + 1));
+    for row in pixels.chunks(width.max(1)) {
+        raw.push(0);
+        raw.extend(row.iter().flatten());
+    }
+    write_png_chunk(b"IDAT", &zlib_stored(&raw), out)?;
+    write_png_chunk(b"IEND", &[], out)
+}
+
+/// Writes a looping animated GIF. Each frame holds `width * height` indices into `palette`,
+/// in row-major order, and is shown for `delay` hundredths of a second. Returns an
+/// `InvalidInput` error, before writing anything, if either side is over 65,535 pixels.
+pub fn write_gif<W: Write>(
+    width: usize,
+    height: usize,
+    palette: &[[u8; 3]],
+    frames: &[Vec<u8>],
+    delay: u16,
+    out: &mut W,
+) -> std::io::Result<()> {
+    assert!(palette.len() <= 256, "Too many colours for a GIF palette. ");
+    let (width_bytes, height_bytes) = (gif_dimension(width)?, gif_dimension(height)?);
+    out.write_all(b"GIF89a")?;
+    out.write_all(&width_bytes)?;
+    out.write_all(&height_bytes)?;
+    // A global colour table of 256 entries, with 8 bits per primary.
+    out.write_all(&[0xf7, 0, 0])?;
+    for i in 0..256 {
+        out.write_all(palette.get(i).unwrap_or(&[0, 0, 0]))?;
+    }
+    // Loop forever.
+    out.write_all(b"\x21\xff\x0bNETSCAPE2.0\x03\x01\x00\x00\x00")?;
+
+    for frame in frames {
+        assert_eq!(
+            frame.len(),
+            width * height,
+            "Pixel count does not match size. "
+        );
+        out.write_all(&[0x21, 0xf9, 4, 0])?;
+        out.write_all(&delay.to_le_bytes())?;
+        out.write_all(&[0, 0, 0x2c, 0, 0, 0, 0])?;
+        out.write_all(&width_bytes)?;
+        out.write_all(&height_bytes)?;
+        out.write_all(&[0, 8])?;
+        for block in lzw_stored(frame).chunks(255) {
+            out.write_all(&[block.len() as u8])?;
+            out.write_all(block)?;
+        }
+        out.write_all(&[0])?;
+    }
+    out.write_all(&[0x3b])
+}
+
+/// A GIF image side as little-endian bytes, or an error if it does not fit in 16 bits.
+pub fn gif_dimension(pixels: usize) -> std::io::Result<[u8; 2]> {
+    u16::try_from(pixels).map(u16::to_le_bytes).map_err(|_| {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("{} pixels is too large for a GIF image. ", pixels),
+        )
+    })
+}
+
+/// Encodes 8-bit indices as GIF LZW codes without any compression. The table is cleared
+/// before it grows past 9-bit codes, so every index can be written as a literal code.
+fn lzw_stored(indices: &[u8]) -> Vec<u8> {
+    const CLEAR: u32 = 256;
+    const END: u32 = 257;
+    let mut codes = vec![];
+    for chunk in indices.chunks(254) {
+        codes.push(CLEAR);
+        codes.extend(chunk.iter().map(|i| *i as u32));
+    }
+    codes.push(END);
+
+    let mut bytes = Vec::with_capacity(codes.len() * 9 / 8 + 1);
+    let (mut acc, mut n_bits) = (0_u32, 0);
+    for code in codes {
+        acc |= code << n_bits;
+        n_bits += 9;
+        while n_bits >= 8 {
+            bytes.push(acc as u8);
+            acc >>= 8;
+            n_bits -= 8;
+        }
+    }
+    if n_bits > 0 {
+        bytes.push(acc as u8);
+    }
+    bytes
+}
+
+fn write_png_chunk<W: Write>(kind: &[u8; 4], data: &[u8], out: &mut W) -> std::io::Result<()> {
+    out.write_all(&(data.len() as u32).to_be_bytes())?;
+    out.write_all(kind)?;
+    out.write_all(data)?;
+    let crc = crc32(kind.iter().chain(data.iter()));
+    out.write_all(&crc.to_be_bytes())
+}
+
+/// Wraps `data` in a zlib stream of uncompressed deflate blocks.
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let mut stream = vec![0x78, 0x01];
+    let mut blocks = data.chunks(u16::MAX as usize).peekable();
+    if blocks.peek().is_none() {
+        stream.extend([1, 0, 0, 0xff, 0xff]);
+    }
+    while let Some(block) = blocks.next() {
+        stream.push(blocks.peek().is_none() as u8);
+        stream.extend((block.len() as u16).to_le_bytes());
+        stream.extend((!(block.len() as u16)).to_le_bytes());
+        stream.extend(block);
+    }
+
+    let (mut a, mut b) = (1_u32, 0_u32);
+    for byte in data {
+        a = (a + *byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    stream.extend((b << 16 | a).to_be_bytes());
+    stream
+}
+
+fn crc32<'a>(bytes: impl Iterator<Item = &'a u8>) -> u32 {
+    let mut crc = 0xffff_ffff_u32;
+    for byte in bytes {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 {
+                0xedb8_8320 ^ (crc >> 1)
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{crc32, lzw_stored, write_gif, write_pbm, write_png_bitmap, zlib_stored};
+
+    #[test]
+    fn image_pbm() {
+        let mut out = vec![];
+        write_pbm(&[vec![true, false], vec![false, true]], &mut out).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "P1\n2 2\n1 0\n0 1\n");
+    }
+
+    #[test]
+    fn image_png() {
+        assert_eq!(crc32(b"IEND".iter()), 0xae42_6082);
+        // Adler-32 of "Wikipedia", as given in its own article.
+        assert!(zlib_stored(b"Wikipedia").ends_with(&0x11e6_0398_u32.to_be_bytes()));
+
+        let mut out = vec![];
+        write_png_bitmap(&[vec![true, false, true]], &mut out).unwrap();
+        assert!(out.starts_with(b"\x89PNG\r\n\x1a\n\x00\x00\x00\x0dIHDR"));
+        assert!(out.ends_with(b"IEND\xae\x42\x60\x82"));
+        // Signature, three chunk headers and footers, the header data, and the image data.
+        assert_eq!(out.len(), 8 + 3 * 12 + 13 + (2 + 5 + 10 + 4));
+    }
+
+    #[test]
+    fn image_gif() {
+        // Clear (256), 1, 2 and end (257), packed as 9-bit codes from the low bit up.
+        assert_eq!(lzw_stored(&[1, 2]), vec![0x00, 0x03, 0x08, 0x08, 0x08]);
+
+        let mut out = vec![];
+        write_gif(
+            2,
+            1,
+            &[[255, 255, 255], [0, 0, 0]],
+            &[vec![0, 1]],
+            10,
+            &mut out,
+        )
+        .unwrap();
+        assert!(out.starts_with(b"GIF89a\x02\x00\x01\x00\xf7"));
+        assert_eq!(out.last(), Some(&0x3b));
+        // Header, palette, loop extension, then one frame holding five bytes of codes.
+        assert_eq!(out.len(), 13 + 768 + 19 + (8 + 10 + 1 + 6 + 1) + 1);
+
+        let mut out = vec![];
+        let err = write_gif(70_000, 0, &[[0, 0, 0]], &[], 10, &mut out).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+        assert!(out.is_empty());
+    }
+}
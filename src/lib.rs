@@ -1,6 +1,10 @@
 pub mod days;
 pub mod err;
+pub mod image;
 pub mod io;
+pub mod math;
+pub mod ocr;
 pub mod rock_paper_scissors;
+pub mod worry;
 
 pub use self::io::*;
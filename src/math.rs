@@ -0,0 +1,10 @@
+//! Integer helpers shared between puzzles.
+
+/// The greatest common divisor of `a` and `b`, which is never negative. `gcd(0, 0)` is zero.
+pub fn gcd(a: i64, b: i64) -> i64 {
+    if b == 0 {
+        a.abs()
+    } else {
+        gcd(b, a % b)
+    }
+}
@@ -0,0 +1,121 @@
+//! Reads the block letters drawn by Advent of Code puzzles, such as the day 10 CRT.
+//!
+//! Letters are 4 pixels wide and 6 tall, with a blank column between each, so glyph `k`
+//! occupies columns `5k..5k + 4` of the image.
+
+use crate::err::Error;
+
+const GLYPH_WIDTH: usize = 4;
+const GLYPH_PITCH: usize = 5;
+const GLYPH_HEIGHT: usize = 6;
+
+/// The known letters, each as six rows of four pixels.
+const FONT: [(char, [&str; GLYPH_HEIGHT]); 18] = [
+    ('A', [".##.", "#..#", "#..#", "####", "#..#", "#..#"]),
+    ('B', ["###.", "#..#", "###.", "#..#", "#..#", "###."]),
+    ('C', [".##.", "#..#", "#...", "#...", "#..#", ".##."]),
+    ('E', ["####", "#...", "###.", "#...", "#...", "####"]),
+    ('F', ["####", "#...", "###.", "#...", "#...", "#..."]),
+    ('G', [".##.", "#..#", "#...", "#.##", "#..#", ".###"]),
+    ('H', ["#..#", "#..#", "####", "#..#", "#..#", "#..#"]),
+    ('I', [".###", "..#.", "..#.", "..#.", "..#.", ".###"]),
+    ('J', ["..##", "...#", "...#", "...#", "#..#", ".##."]),
+    ('K', ["#..#", "#.#.", "##..", "#.#.", "#.#.", "#..#"]),
+    ('L', ["#...", "#...", "#...", "#...", "#...", "####"]),
+    ('O', [".##.", "#..#", "#..#", "#..#", "#..#", ".##."]),
+    ('P', ["###.", "#..#", "#..#", "###.", "#...", "#..."]),
+    ('R', ["###.", "#..#", "#..#", "###.", "#.#.", "#..#"]),
+    ('S', [".###", "#...", "#...", ".##.", "...#", "###."]),
+    ('U', ["#..#", "#..#", "#..#", "#..#", "#..#", ".##."]),
+    ('Y', ["#...", "#...", ".#.#", "..#.", "..#.", "..#."]),
+    ('Z', ["####", "...#", "..#.", ".#..", "#...", "####"]),
+];
+
+/// Reads the letters from a bitmap, indexed as `bitmap[row][column]`, where `true` is a lit
+/// pixel. A blank cell is read as a space.
+pub fn recognise_bitmap(bitmap: &[Vec<bool>]) -> Result<String, Error> {
+    if bitmap.len() != GLYPH_HEIGHT {
+        return Err(Error::ParseError {
+            line: 1,
+            column: 1,
+            message: format!(
+                "Expected an image {} rows tall, found {}. ",
+                GLYPH_HEIGHT,
+                bitmap.len()
+            ),
+        });
+    }
+
+    let width = bitmap.iter().map(|row| row.len()).max().unwrap_or(0);
+    let pixel = |row: usize, col: usize| *bitmap[row].get(col).unwrap_or(&false);
+    (0..(width + 1) / GLYPH_PITCH)
+        .map(|index| {
+            let glyph: Vec<String> = (0..GLYPH_HEIGHT)
+                .map(|row| {
+                    (0..GLYPH_WIDTH)
+                        .map(|col| {
+                            if pixel(row, index * GLYPH_PITCH + col) {
+                                '#'
+                            } else {
+                                '.'
+                            }
+                        })
+                        .collect()
+                })
+                .collect();
+
+            if glyph.iter().all(|row| !row.contains('#')) {
+                return Ok(' ');
+            }
+            FONT.iter()
+                .find(|(_, rows)| rows.iter().zip(glyph.iter()).all(|(a, b)| a == b))
+                .map(|(letter, _)| *letter)
+                .ok_or_else(|| Error::UnknownGlyph {
+                    index,
+                    glyph: glyph.join("\n"),
+                })
+        })
+        .collect()
+}
+
+/// Reads the letters from text, one line per row, where `#` is a lit pixel.
+pub fn recognise(image: &str) -> Result<String, Error> {
+    let bitmap: Vec<Vec<bool>> = image
+        .lines()
+        .filter(|line| !line.is_empty())
+        .map(|line| line.chars().map(|ch| ch == '#').collect())
+        .collect();
+    recognise_bitmap(&bitmap)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{recognise, FONT};
+    use crate::err::Error;
+
+    #[test]
+    fn ocr_font() {
+        // Draw every letter in the font side by side and read them back.
+        let image: String = (0..6)
+            .map(|row| {
+                FONT.iter()
+                    .map(|(_, rows)| format!("{}.", rows[row]))
+                    .collect::<String>()
+                    + "\n"
+            })
+            .collect();
+        assert_eq!(recognise(&image).unwrap(), "ABCEFGHIJKLOPRSUYZ");
+    }
+
+    #[test]
+    fn ocr_unknown_glyph() {
+        let image = ".##..#..#\n#..#.#..#\n#..#.####\n####.#..#\n#..#.#..#\n#..#..#.#\n";
+        match recognise(image) {
+            Err(Error::UnknownGlyph { index, glyph }) => {
+                assert_eq!(index, 1);
+                assert!(glyph.ends_with(".#.#"));
+            }
+            other => panic!("Expected an unknown glyph, got {:?}", other),
+        }
+    }
+}
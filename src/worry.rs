@@ -0,0 +1,291 @@
+//! Numeric backends for the worry levels tracked in day 11.
+//!
+//! Every operation is checked, so that a simulation can report an overflow rather than
+//! silently wrapping. [`BigUint`] never overflows, and can be used for exact simulations
+//! where the worry levels grow without bound.
+
+use std::cmp::Ordering;
+use std::fmt::{Debug, Display};
+use std::hash::Hash;
+
+pub trait Worry: Clone + Debug + Display + PartialEq + Eq + Hash {
+    /// Converts from an `i64`, returning `None` if the value cannot be represented.
+    fn from_i64(value: i64) -> Option<Self>;
+    fn checked_add(&self, rhs: &Self) -> Option<Self>;
+    fn checked_sub(&self, rhs: &Self) -> Option<Self>;
+    fn checked_mul(&self, rhs: &Self) -> Option<Self>;
+    /// Division, returning `None` on overflow or division by zero.
+    fn checked_div(&self, rhs: &Self) -> Option<Self>;
+    /// Remainder, returning `None` on overflow or division by zero.
+    fn checked_rem(&self, rhs: &Self) -> Option<Self>;
+    fn is_zero(&self) -> bool;
+}
+
+macro_rules! impl_worry_for_primitive {
+    ($($t:ty),*) => {
+        $(
+            impl Worry for $t {
+                fn from_i64(value: i64) -> Option<Self> {
+                    <$t>::try_from(value).ok()
+                }
+
+                fn checked_add(&self, rhs: &Self) -> Option<Self> {
+                    <$t>::checked_add(*self, *rhs)
+                }
+
+                fn checked_sub(&self, rhs: &Self) -> Option<Self> {
+                    <$t>::checked_sub(*self, *rhs)
+                }
+
+                fn checked_mul(&self, rhs: &Self) -> Option<Self> {
+                    <$t>::checked_mul(*self, *rhs)
+                }
+
+                fn checked_div(&self, rhs: &Self) -> Option<Self> {
+                    <$t>::checked_div(*self, *rhs)
+                }
+
+                fn checked_rem(&self, rhs: &Self) -> Option<Self> {
+                    <$t>::checked_rem(*self, *rhs)
+                }
+
+                fn is_zero(&self) -> bool {
+                    *self == 0
+                }
+            }
+        )*
+    };
+}
+
+impl_worry_for_primitive!(i64, i128, u64, u128);
+
+/// A minimal arbitrary-precision unsigned integer, stored as little-endian 32-bit limbs
+/// with no trailing zero limbs.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct BigUint {
+    limbs: Vec<u32>,
+}
+
+impl BigUint {
+    pub fn from_u64(value: u64) -> BigUint {
+        let mut big = BigUint {
+            limbs: vec![value as u32, (value >> 32) as u32],
+        };
+        big.normalise();
+        big
+    }
+
+    /// Returns the value as a `u64`, if it fits.
+    pub fn to_u64(&self) -> Option<u64> {
+        match self.limbs[..] {
+            [] => Some(0),
+            [lo] => Some(lo as u64),
+            [lo, hi] => Some(lo as u64 | (hi as u64) << 32),
+            _ => None,
+        }
+    }
+
+    /// The number of significant bits.
+    pub fn bits(&self) -> usize {
+        match self.limbs.last() {
+            Some(top) => self.limbs.len() * 32 - top.leading_zeros() as usize,
+            None => 0,
+        }
+    }
+
+    fn bit(&self, i: usize) -> bool {
+        self.limbs[i / 32] >> (i % 32) & 1 == 1
+    }
+
+    fn normalise(&mut self) {
+        while self.limbs.last() == Some(&0) {
+            self.limbs.pop();
+        }
+    }
+
+    /// Divides by a single limb, returning the quotient and remainder.
+    fn divrem_small(&self, rhs: u32) -> (BigUint, u32) {
+        let mut rem = 0_u64;
+        let mut limbs = vec![0; self.limbs.len()];
+        for (i, limb) in self.limbs.iter().enumerate().rev() {
+            let cur = rem << 32 | *limb as u64;
+            limbs[i] = (cur / rhs as u64) as u32;
+            rem = cur % rhs as u64;
+        }
+        let mut quot = BigUint { limbs };
+        quot.normalise();
+        (quot, rem as u32)
+    }
+
+    /// Binary long division, returning the quotient and remainder.
+    fn divrem(&self, rhs: &BigUint) -> Option<(BigUint, BigUint)> {
+        match rhs.limbs[..] {
+            [] => None,
+            [small] => {
+                let (quot, rem) = self.divrem_small(small);
+                Some((quot, BigUint::from_u64(rem as u64)))
+            }
+            _ => {
+                let mut quot = BigUint {
+                    limbs: vec![0; self.limbs.len()],
+                };
+                let mut rem = BigUint::default();
+                for i in (0..self.bits()).rev() {
+                    rem = rem.checked_add(&rem).unwrap();
+                    if self.bit(i) {
+                        rem = rem.checked_add(&BigUint::from_u64(1)).unwrap();
+                    }
+                    if rem >= *rhs {
+                        rem = rem.checked_sub(rhs).unwrap();
+                        quot.limbs[i / 32] |= 1 << (i % 32);
+                    }
+                }
+                quot.normalise();
+                Some((quot, rem))
+            }
+        }
+    }
+}
+
+impl Ord for BigUint {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.limbs
+            .len()
+            .cmp(&other.limbs.len())
+            .then_with(|| self.limbs.iter().rev().cmp(other.limbs.iter().rev()))
+    }
+}
+
+impl PartialOrd for BigUint {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Display for BigUint {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.limbs.is_empty() {
+            return write!(f, "0");
+        }
+
+        // Peel off nine decimal digits at a time, least significant first.
+        let mut chunks = vec![];
+        let mut value = self.clone();
+        while !value.limbs.is_empty() {
+            let (quot, rem) = value.divrem_small(1_000_000_000);
+            chunks.push(rem);
+            value = quot;
+        }
+        write!(f, "{}", chunks.pop().unwrap())?;
+        for chunk in chunks.iter().rev() {
+            write!(f, "{:09}", chunk)?;
+        }
+        Ok(())
+    }
+}
+
+impl Worry for BigUint {
+    fn from_i64(value: i64) -> Option<Self> {
+        u64::try_from(value).ok().map(BigUint::from_u64)
+    }
+
+    fn checked_add(&self, rhs: &Self) -> Option<Self> {
+        let mut limbs = Vec::with_capacity(self.limbs.len().max(rhs.limbs.len()) + 1);
+        let mut carry = 0_u64;
+        for i in 0..self.limbs.len().max(rhs.limbs.len()) {
+            let sum = *self.limbs.get(i).unwrap_or(&0) as u64
+                + *rhs.limbs.get(i).unwrap_or(&0) as u64
+                + carry;
+            limbs.push(sum as u32);
+            carry = sum >> 32;
+        }
+        limbs.push(carry as u32);
+        let mut big = BigUint { limbs };
+        big.normalise();
+        Some(big)
+    }
+
+    fn checked_sub(&self, rhs: &Self) -> Option<Self> {
+        if *self < *rhs {
+            return None;
+        }
+
+        let mut limbs = Vec::with_capacity(self.limbs.len());
+        let mut borrow = 0_i64;
+        for (i, limb) in self.limbs.iter().enumerate() {
+            let mut diff = *limb as i64 - *rhs.limbs.get(i).unwrap_or(&0) as i64 - borrow;
+            borrow = 0;
+            if diff < 0 {
+                diff += 1 << 32;
+                borrow = 1;
+            }
+            limbs.push(diff as u32);
+        }
+        let mut big = BigUint { limbs };
+        big.normalise();
+        Some(big)
+    }
+
+    fn checked_mul(&self, rhs: &Self) -> Option<Self> {
+        let mut limbs = vec![0_u32; self.limbs.len() + rhs.limbs.len()];
+        for (i, a) in self.limbs.iter().enumerate() {
+            let mut carry = 0_u64;
+            for (j, b) in rhs.limbs.iter().enumerate() {
+                let cur = limbs[i + j] as u64 + *a as u64 * *b as u64 + carry;
+                limbs[i + j] = cur as u32;
+                carry = cur >> 32;
+            }
+            limbs[i + rhs.limbs.len()] = carry as u32;
+        }
+        let mut big = BigUint { limbs };
+        big.normalise();
+        Some(big)
+    }
+
+    fn checked_div(&self, rhs: &Self) -> Option<Self> {
+        self.divrem(rhs).map(|(quot, _)| quot)
+    }
+
+    fn checked_rem(&self, rhs: &Self) -> Option<Self> {
+        self.divrem(rhs).map(|(_, rem)| rem)
+    }
+
+    fn is_zero(&self) -> bool {
+        self.limbs.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BigUint, Worry};
+
+    #[test]
+    fn big_uint_arithmetic() {
+        let a = BigUint::from_u64(u64::MAX);
+        let b = BigUint::from_u64(12_345_678_901);
+
+        let product = a.checked_mul(&b).unwrap();
+        assert_eq!(
+            product.to_string(),
+            (u64::MAX as u128 * 12_345_678_901_u128).to_string()
+        );
+        assert_eq!(product.checked_div(&b).unwrap(), a);
+        assert_eq!(product.checked_div(&a).unwrap(), b);
+        assert!(product.checked_rem(&a).unwrap().is_zero());
+
+        let sum = product.checked_add(&BigUint::from_u64(7)).unwrap();
+        assert_eq!(sum.checked_rem(&b).unwrap(), BigUint::from_u64(7));
+        assert_eq!(sum.checked_sub(&product).unwrap(), BigUint::from_u64(7));
+        assert_eq!(product.checked_sub(&sum), None);
+        assert_eq!(a.checked_div(&BigUint::default()), None);
+    }
+
+    #[test]
+    fn big_uint_display() {
+        assert_eq!(BigUint::default().to_string(), "0");
+        let big = (0..4).fold(BigUint::from_u64(1), |acc, _| {
+            acc.checked_mul(&BigUint::from_u64(1_000_000_007)).unwrap()
+        });
+        assert_eq!(big.to_string(), "1000000028000000294000001372000002401");
+    }
+}